@@ -0,0 +1,407 @@
+use std::future::{self, Future};
+use std::io::{self, Cursor, Error, ErrorKind, Result, Write};
+use std::net::SocketAddr;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+
+use crate::http::{error_status, Connection, HttpError, Request, RequestScanner, SharedBuffer};
+use crate::response::Response;
+use crate::route::Params;
+use crate::server::{is_idle_error, ShutdownSignal};
+use crate::sse::is_disconnect;
+use crate::{panic_error, Handler, Route, Router};
+
+// Writes a connection's writer task may lag behind before handlers block on it.
+const OUTPUT_BUFFERS: usize = 16;
+
+// Wakes connections waiting for a request when the server stops, and tells handlers.
+type Stopping = (watch::Receiver<bool>, ShutdownSignal);
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+pub trait AsyncHandler: Send + Sync + 'static {
+    fn handle(&self, req: Request) -> BoxFuture<Result<Response>>;
+}
+
+impl<F, Fut> AsyncHandler for F
+where
+    F: Send + Sync + Fn(Request) -> Fut + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    fn handle(&self, req: Request) -> BoxFuture<Result<Response>> {
+        Box::pin((*self)(req))
+    }
+}
+
+// Lets an async handler sit behind synchronous middleware or be served by Router::listen.
+struct BlockingHandler(Arc<dyn AsyncHandler>);
+
+impl Handler for BlockingHandler {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        let future = self.0.handle(conn.read_request()?);
+        match Handle::try_current() {
+            Ok(handle) => handle.block_on(future),
+            Err(_) => fallback_runtime()?.block_on(future),
+        }
+    }
+}
+
+fn fallback_runtime() -> Result<&'static Runtime> {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = Runtime::new()?;
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+impl Router {
+    pub fn add_async_handler<H: AsyncHandler>(
+        &mut self,
+        method: &str,
+        path: &str,
+        handler: H,
+    ) -> &mut Route {
+        let handler: Arc<dyn AsyncHandler> = Arc::new(handler);
+        let route = self.add_handler(method, path, BlockingHandler(handler.clone()));
+        route.async_handler = Some(handler);
+        route
+    }
+
+    // Idle connections only cost a task. Requests are buffered in memory and parsed by the same
+    // Connection as Router::listen uses; synchronous handlers and middleware run on tokio's
+    // blocking pool, and responses are written to the client as they are produced.
+    pub async fn listen_async(self, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve_async(listener, future::pending()).await
+    }
+
+    // Serves the connections of listener until shutdown completes, e.g. on a signal. Then no
+    // more connections are accepted, idle ones are closed and the others may finish their
+    // request within the shutdown timeout, after which they are dropped.
+    pub async fn serve_async<F>(self, listener: TcpListener, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        #[cfg(feature = "tls")]
+        {
+            if self.tls.is_some() {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "TLS is only supported by Router::listen",
+                ));
+            }
+        }
+        let router = Arc::new(self);
+        let (stop, stopping) = watch::channel(false);
+        let shutdown_signal = ShutdownSignal::default();
+        let mut connections = JoinSet::new();
+        let mut shutdown = pin!(shutdown);
+        loop {
+            let (mut stream, peer_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        router.error_log.log(&e.to_string());
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            while connections.try_join_next().is_some() {}
+
+            let router = router.clone();
+            if connections.len() >= router.max_connections {
+                tokio::spawn(async move {
+                    let e = HttpError::new(503, "too many connections").into();
+                    if let Ok(response) = error_response(e) {
+                        let _ = write_all(&mut stream, &response, router.write_timeout).await;
+                    }
+                });
+                continue;
+            }
+            let stopping = (stopping.clone(), shutdown_signal.clone());
+            connections.spawn(async move {
+                if let Err(e) = router
+                    .clone()
+                    .serve_connection_async(stream, peer_addr, stopping)
+                    .await
+                {
+                    if !is_idle_error(&e) && !is_disconnect(&e) {
+                        router.error_log.log(&e.to_string());
+                    }
+                }
+            });
+        }
+
+        drop(listener);
+        shutdown_signal.set();
+        let _ = stop.send(true);
+        let drained = tokio::time::timeout(router.shutdown_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            let remaining = connections.len();
+            connections.shutdown().await;
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{} connections still open after shutdown timeout",
+                    remaining
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn serve_connection_async(
+        self: Arc<Self>,
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+        stopping: Stopping,
+    ) -> Result<()> {
+        let (mut reader, writer) = stream.into_split();
+        let (output, pending) = mpsc::channel(OUTPUT_BUFFERS);
+        let writing = tokio::spawn(write_output(writer, pending, self.write_timeout));
+        let result = self
+            .serve_requests(&mut reader, peer_addr, output, stopping)
+            .await;
+        let written = writing.await.map_err(Error::other)?;
+        match result {
+            // Handlers only see that the writer task stopped; its error tells why
+            Err(e) if is_disconnect(&e) => written.and(Err(e)),
+            result => result.and(written),
+        }
+    }
+
+    async fn serve_requests(
+        self: Arc<Self>,
+        stream: &mut OwnedReadHalf,
+        peer_addr: SocketAddr,
+        output: mpsc::Sender<Vec<u8>>,
+        (mut stopping, shutdown_signal): Stopping,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut requests = 0;
+        loop {
+            let len = match self.read_request(stream, &mut buf, &mut stopping).await {
+                Ok(Some(len)) => len,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    let response = error_response(e)?;
+                    return output.send(response).await.map_err(|_| disconnected());
+                }
+            };
+            let request: Vec<u8> = buf.drain(..len).collect();
+            let mut conn = Connection::from_io(Cursor::new(request), OutputWriter(output.clone()));
+            conn.set_limits(self.limits);
+            conn.set_peer_addr(peer_addr);
+            conn.set_shutdown_signal(shutdown_signal.clone());
+            conn.next_request()?;
+
+            requests += 1;
+            if requests >= self.max_requests_per_connection || *stopping.borrow() {
+                conn.set_keep_alive(false);
+            }
+
+            let conn = self.clone().dispatch_async(conn).await?;
+            if !conn.keep_alive() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Responses are sent from the blocking pool, since writing them waits for the client.
+    async fn dispatch_async(self: Arc<Self>, mut conn: Connection) -> Result<Connection> {
+        if let Some((handler, params)) = self.get_async_handler(&conn.method, &conn.path) {
+            conn.params = params;
+            // Spawned so that a panicking handler fails its task instead of the connection's.
+            let result = match conn.read_request() {
+                Ok(request) => match tokio::spawn(handler.handle(request)).await {
+                    Ok(result) => result,
+                    Err(e) if e.is_panic() => Err(panic_error(e.into_panic().as_ref())),
+                    Err(e) => Err(Error::other(e)),
+                },
+                Err(e) => Err(e),
+            };
+            return run_blocking(move || {
+                let response = match result {
+                    Ok(response) => response,
+                    Err(e) => self.render_error(&mut conn, e)?,
+                };
+                conn.send(response)?;
+                Ok(conn)
+            })
+            .await;
+        }
+
+        run_blocking(move || {
+            let response = self.dispatch(&mut conn)?;
+            conn.send(response)?;
+            Ok(conn)
+        })
+        .await
+    }
+
+    // Async handlers are awaited directly only when no middleware has to run before them.
+    fn get_async_handler(
+        &self,
+        method: &str,
+        path: &str,
+    ) -> Option<(Arc<dyn AsyncHandler>, Params)> {
+        if !self.middlewares.is_empty() {
+            return None;
+        }
+        let (routes, params) = self.routes.find(path)?;
+        let route = routes.get(method)?;
+        if !route.middlewares.is_empty() {
+            return None;
+        }
+        route.async_handler.clone().map(|handler| (handler, params))
+    }
+}
+
+async fn run_blocking<F>(f: F) -> Result<Connection>
+where
+    F: FnOnce() -> Result<Connection> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(Error::other)?
+}
+
+// The response to a request that RequestScanner rejected with an http::HttpError, which closes
+// the connection.
+fn error_response(e: Error) -> Result<Vec<u8>> {
+    let status = match error_status(&e) {
+        Some(status) => status,
+        None => return Err(e),
+    };
+    let output = SharedBuffer::default();
+    let mut conn = Connection::from_io(io::empty(), output.clone());
+    conn.set_keep_alive(false);
+    conn.send(Response::new(status))?;
+    Ok(output.take())
+}
+
+// Passes what a Connection writes on to the writer task of its client, blocking while that is
+// OUTPUT_BUFFERS writes behind. Must not be used on the runtime's own threads.
+struct OutputWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| disconnected())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+async fn write_output(
+    mut stream: OwnedWriteHalf,
+    mut output: mpsc::Receiver<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    while let Some(buf) = output.recv().await {
+        write_all(&mut stream, &buf, timeout).await?;
+    }
+    Ok(())
+}
+
+async fn write_all<W>(stream: &mut W, buf: &[u8], timeout: Option<Duration>) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, stream.write_all(buf))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "write timed out"))?,
+        None => stream.write_all(buf).await,
+    }
+}
+
+fn disconnected() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "client disconnected")
+}
+
+impl Router {
+    // Reads until buf holds a whole request and returns its length. The keep-alive timeout
+    // applies until the request starts, then the header timeout until its head is complete and
+    // the read timeout to each read. Waiting for a request ends when the server stops.
+    async fn read_request(
+        &self,
+        stream: &mut OwnedReadHalf,
+        buf: &mut Vec<u8>,
+        stopping: &mut watch::Receiver<bool>,
+    ) -> Result<Option<usize>> {
+        let mut scanner = RequestScanner::new(self.limits);
+        let mut chunk = [0; 8192];
+        let mut deadline = None;
+        loop {
+            if let Some(len) = scanner.scan(buf)? {
+                return Ok(Some(len));
+            }
+            let started = !buf.is_empty();
+            if scanner.is_head_complete() {
+                deadline = None;
+            } else if started && deadline.is_none() {
+                deadline = self.header_timeout.map(|timeout| Instant::now() + timeout);
+            }
+            let mut timeout = if started {
+                self.read_timeout
+            } else {
+                self.keep_alive_timeout
+            };
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+            }
+            let read = async {
+                match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, stream.read(&mut chunk))
+                        .await
+                        .ok(),
+                    None => Some(stream.read(&mut chunk).await),
+                }
+            };
+            let read = if started {
+                read.await
+            } else {
+                tokio::select! {
+                    read = read => read,
+                    _ = stopping.wait_for(|&stopping| stopping) => return Ok(None),
+                }
+            };
+            let n = match read {
+                Some(n) => n?,
+                None if deadline.is_some_and(|d| Instant::now() >= d) => {
+                    return Err(HttpError::new(408, "request head not received in time").into())
+                }
+                None if started => return Err(Error::new(ErrorKind::TimedOut, "read timed out")),
+                None => return Ok(None),
+            };
+            if n == 0 {
+                return if buf.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "connection closed in the middle of a request",
+                    ))
+                };
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
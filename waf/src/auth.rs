@@ -0,0 +1,644 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::{self, KeyPair, RsaKeyPair, UnparsedPublicKey};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{PrivatePkcs8KeyDer, SubjectPublicKeyInfoDer};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use yasna::models::ObjectIdentifier;
+
+use crate::extract::FromRequest;
+use crate::http::{Connection, HttpError};
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+
+// Who made the request, as established by one of the authentication middlewares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    subject: String,
+    roles: Vec<String>,
+    claims: Option<Claims>,
+}
+
+impl Identity {
+    pub fn new(subject: &str) -> Self {
+        Identity {
+            subject: subject.to_string(),
+            roles: Vec::new(),
+            claims: None,
+        }
+    }
+
+    pub fn with_roles(mut self, roles: &[&str]) -> Self {
+        self.roles = roles.iter().map(|role| role.to_string()).collect();
+        self
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn roles(&self) -> &[String] {
+        &self.roles
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    // The token's claims when authenticated by JwtAuth.
+    pub fn claims(&self) -> Option<&Claims> {
+        self.claims.as_ref()
+    }
+}
+
+impl Connection {
+    pub fn identity(&self) -> Option<&Identity> {
+        self.extensions().get::<Identity>()
+    }
+}
+
+// Fails with 401 for anonymous requests.
+impl FromRequest for Identity {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        conn.identity()
+            .cloned()
+            .ok_or_else(|| HttpError::new(401, "not authenticated").into())
+    }
+}
+
+// The WWW-Authenticate challenge of the authentication in use, left for guards when a request
+// passes anonymously.
+struct Challenge(String);
+
+type BasicVerifier = dyn Fn(&str, &str) -> Option<Identity> + Send + Sync;
+type BearerVerifier = dyn Fn(&str) -> Option<Identity> + Send + Sync;
+
+// HTTP Basic authentication:
+//
+//     router.add_middleware(BasicAuth::new("admin", |user: &str, password: &str| {
+//         if user == "admin" && constant_time_eq(password.as_bytes(), ADMIN_PASSWORD) {
+//             Some(Identity::new(user))
+//         } else {
+//             None
+//         }
+//     }));
+//
+// Requests without valid credentials get 401 unless optional() was called, in which case those
+// without any credentials pass anonymously.
+pub struct BasicAuth {
+    realm: String,
+    verify: Arc<BasicVerifier>,
+    optional: bool,
+}
+
+impl BasicAuth {
+    pub fn new<F>(realm: &str, verify: F) -> Self
+    where
+        F: Fn(&str, &str) -> Option<Identity> + Send + Sync + 'static,
+    {
+        BasicAuth {
+            realm: realm.to_string(),
+            verify: Arc::new(verify),
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    fn challenge(&self) -> String {
+        format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            escape_quoted(&self.realm)
+        )
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let credentials = match credentials(conn, "Basic") {
+            Some(credentials) => credentials,
+            None if self.optional => {
+                conn.extensions_mut().insert(Challenge(self.challenge()));
+                return next.run(conn);
+            }
+            None => return Ok(unauthorized(&self.challenge())),
+        };
+        let identity = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                let (user, password) = decoded.split_once(':')?;
+                (self.verify)(user, password)
+            });
+        match identity {
+            Some(identity) => {
+                conn.extensions_mut().insert(identity);
+                next.run(conn)
+            }
+            None => Ok(unauthorized(&self.challenge())),
+        }
+    }
+}
+
+// Bearer token authentication (RFC 6750) with opaque tokens checked by a function, such as a
+// lookup of API keys.
+pub struct BearerAuth {
+    realm: String,
+    verify: Arc<BearerVerifier>,
+    optional: bool,
+}
+
+impl BearerAuth {
+    pub fn new<F>(realm: &str, verify: F) -> Self
+    where
+        F: Fn(&str) -> Option<Identity> + Send + Sync + 'static,
+    {
+        BearerAuth {
+            realm: realm.to_string(),
+            verify: Arc::new(verify),
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let verify = |token: &str| (self.verify)(token).ok_or("invalid token".to_string());
+        bearer(conn, next, &self.realm, self.optional, verify)
+    }
+}
+
+// Bearer authentication with JSON Web Tokens:
+//
+//     let jwt = Jwt::new(JwtKey::hs256(secret)).with_issuer("https://auth.example.com");
+//     router.add_middleware(JwtAuth::new("api", jwt));
+//
+// The identity's subject is the "sub" claim, which tokens must have, and its roles come from a
+// "roles" array or a space-separated "scope" claim.
+pub struct JwtAuth {
+    realm: String,
+    jwt: Jwt,
+    optional: bool,
+}
+
+impl JwtAuth {
+    pub fn new(realm: &str, jwt: Jwt) -> Self {
+        JwtAuth {
+            realm: realm.to_string(),
+            jwt,
+            optional: false,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+impl Middleware for JwtAuth {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let verify = |token: &str| {
+            let claims = self.jwt.verify(token).map_err(|e| e.to_string())?;
+            let subject = claims.subject().ok_or("missing sub claim")?.to_string();
+            Ok(Identity {
+                subject,
+                roles: claims.roles(),
+                claims: Some(claims),
+            })
+        };
+        bearer(conn, next, &self.realm, self.optional, verify)
+    }
+}
+
+fn bearer<F>(
+    conn: &mut Connection,
+    next: Next<'_>,
+    realm: &str,
+    optional: bool,
+    verify: F,
+) -> Result<Response>
+where
+    F: Fn(&str) -> std::result::Result<Identity, String>,
+{
+    let challenge = format!("Bearer realm=\"{}\"", escape_quoted(realm));
+    let token = match credentials(conn, "Bearer") {
+        Some(token) => token,
+        None if optional => {
+            conn.extensions_mut().insert(Challenge(challenge));
+            return next.run(conn);
+        }
+        None => return Ok(unauthorized(&challenge)),
+    };
+    match verify(&token) {
+        Ok(identity) => {
+            conn.extensions_mut().insert(identity);
+            next.run(conn)
+        }
+        Err(description) => Ok(unauthorized(&format!(
+            "{}, error=\"invalid_token\", error_description=\"{}\"",
+            challenge,
+            escape_quoted(&description)
+        ))),
+    }
+}
+
+// Per-route authorization, added after an authentication middleware:
+//
+//     router
+//         .add_handler(METHOD_DELETE, "/users/:id", delete_user)
+//         .add_middleware(Guard::role("admin"));
+//
+// Anonymous requests get 401 with the challenge of the authentication middleware, and
+// authenticated ones that the guard rejects get 403. Without an authentication middleware in
+// front there is no way to authenticate, so anonymous requests get 403 as well.
+pub struct Guard {
+    check: Arc<dyn Fn(&Identity) -> bool + Send + Sync>,
+}
+
+impl Guard {
+    pub fn new<F>(check: F) -> Self
+    where
+        F: Fn(&Identity) -> bool + Send + Sync + 'static,
+    {
+        Guard {
+            check: Arc::new(check),
+        }
+    }
+
+    pub fn authenticated() -> Self {
+        Guard::new(|_| true)
+    }
+
+    pub fn role(role: &str) -> Self {
+        let role = role.to_string();
+        Guard::new(move |identity| identity.has_role(&role))
+    }
+
+    // Passes identities with at least one of the roles.
+    pub fn any_role(roles: &[&str]) -> Self {
+        let roles: Vec<String> = roles.iter().map(|role| role.to_string()).collect();
+        Guard::new(move |identity| roles.iter().any(|role| identity.has_role(role)))
+    }
+}
+
+impl Middleware for Guard {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        match conn.identity() {
+            Some(identity) if (self.check)(identity) => next.run(conn),
+            Some(_) => Ok(Response::new(403)),
+            None => match conn.extensions().get::<Challenge>() {
+                Some(Challenge(challenge)) => Ok(unauthorized(challenge)),
+                None => Ok(Response::new(403)),
+            },
+        }
+    }
+}
+
+// Compares secrets in time independent of where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn credentials(conn: &Connection, scheme: &str) -> Option<String> {
+    let authorization = conn.header("Authorization")?.trim();
+    let (name, credentials) = authorization.split_once(' ')?;
+    if !name.eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+    Some(credentials.trim().to_string())
+}
+
+fn unauthorized(challenge: &str) -> Response {
+    Response::new(401).with_header("WWW-Authenticate", challenge)
+}
+
+fn escape_quoted(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    HS256,
+    RS256,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::HS256 => "HS256",
+            Algorithm::RS256 => "RS256",
+        }
+    }
+}
+
+// A key for signing or verifying tokens. Tokens are only accepted with the algorithm of the
+// key, whatever their header says.
+pub struct JwtKey(KeyKind);
+
+enum KeyKind {
+    Hmac(hmac::Key),
+    RsaPublic(Vec<u8>),
+    RsaPrivate(RsaKeyPair),
+}
+
+impl JwtKey {
+    pub fn hs256(secret: &[u8]) -> Self {
+        JwtKey(KeyKind::Hmac(hmac::Key::new(hmac::HMAC_SHA256, secret)))
+    }
+
+    // Verifies RS256 tokens with a "PUBLIC KEY" PEM, as written by openssl rsa -pubout.
+    pub fn rs256_public_pem(pem: &str) -> Result<Self> {
+        let spki = SubjectPublicKeyInfoDer::from_pem_slice(pem.as_bytes())
+            .map_err(|e| invalid_key(&e.to_string()))?;
+        let key = spki_rsa_public_key(&spki).ok_or_else(|| invalid_key("not an RSA public key"))?;
+        Ok(JwtKey(KeyKind::RsaPublic(key)))
+    }
+
+    // Verifies RS256 tokens with a DER-encoded PKCS#1 RSAPublicKey.
+    pub fn rs256_public_der(der: &[u8]) -> Result<Self> {
+        if !is_rsa_public_key(der) {
+            return Err(invalid_key("not a PKCS#1 RSA public key"));
+        }
+        Ok(JwtKey(KeyKind::RsaPublic(der.to_vec())))
+    }
+
+    // Signs and verifies RS256 tokens with a PKCS#8 "PRIVATE KEY" PEM.
+    pub fn rs256_private_pem(pem: &str) -> Result<Self> {
+        let key = PrivatePkcs8KeyDer::from_pem_slice(pem.as_bytes())
+            .map_err(|e| invalid_key(&e.to_string()))?;
+        let key_pair = RsaKeyPair::from_pkcs8(key.secret_pkcs8_der())
+            .map_err(|e| invalid_key(&e.to_string()))?;
+        Ok(JwtKey(KeyKind::RsaPrivate(key_pair)))
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self.0 {
+            KeyKind::Hmac(_) => Algorithm::HS256,
+            KeyKind::RsaPublic(_) | KeyKind::RsaPrivate(_) => Algorithm::RS256,
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match &self.0 {
+            KeyKind::Hmac(key) => Ok(hmac::sign(key, message).as_ref().to_vec()),
+            KeyKind::RsaPrivate(key_pair) => {
+                let mut signature = vec![0; key_pair.public().modulus_len()];
+                key_pair
+                    .sign(
+                        &signature::RSA_PKCS1_SHA256,
+                        &SystemRandom::new(),
+                        message,
+                        &mut signature,
+                    )
+                    .map_err(|_| Error::other("failed to sign the token"))?;
+                Ok(signature)
+            }
+            KeyKind::RsaPublic(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a public key can't sign tokens",
+            )),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.0 {
+            KeyKind::Hmac(key) => hmac::verify(key, message, signature).is_ok(),
+            KeyKind::RsaPublic(key) => {
+                UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, key)
+                    .verify(message, signature)
+                    .is_ok()
+            }
+            KeyKind::RsaPrivate(key_pair) => UnparsedPublicKey::new(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                key_pair.public_key().as_ref(),
+            )
+            .verify(message, signature)
+            .is_ok(),
+        }
+    }
+}
+
+// The claims of a verified token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims(Map<String, Value>);
+
+impl Claims {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(Value::as_str)
+    }
+
+    pub fn roles(&self) -> Vec<String> {
+        if let Some(Value::Array(roles)) = self.0.get("roles") {
+            return roles
+                .iter()
+                .filter_map(|role| role.as_str().map(str::to_string))
+                .collect();
+        }
+        match self.0.get("scope").and_then(Value::as_str) {
+            Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The claims as an application type.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(Value::Object(self.0.clone()))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+// Signs and verifies compact JWS tokens. Verification checks the signature, "exp" and "nbf"
+// with some leeway for clock skew, and "iss" and "aud" when expected values are set. Tokens
+// without "exp" are rejected unless with_required_claims() says otherwise, since they would be
+// valid forever.
+pub struct Jwt {
+    key: JwtKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+    required_claims: Vec<String>,
+    leeway: Duration,
+}
+
+impl Jwt {
+    pub fn new(key: JwtKey) -> Self {
+        Jwt {
+            key,
+            issuer: None,
+            audience: None,
+            required_claims: vec!["exp".to_string()],
+            leeway: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    // The claims tokens must have, replacing the default of just "exp".
+    pub fn with_required_claims(mut self, claims: &[&str]) -> Self {
+        self.required_claims = claims.iter().map(|claim| claim.to_string()).collect();
+        self
+    }
+
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    // Claims must serialize to a JSON object.
+    pub fn sign<T: serde::Serialize>(&self, claims: &T) -> Result<String> {
+        let header = format!(
+            "{{\"alg\":\"{}\",\"typ\":\"JWT\"}}",
+            self.key.algorithm().name()
+        );
+        let claims = serde_json::to_vec(claims).map_err(Error::other)?;
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header),
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let signature = self.key.sign(message.as_bytes())?;
+        Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims> {
+        let mut parts = token.split('.');
+        let (header, claims, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(header), Some(claims), Some(signature), None) => (header, claims, signature),
+                _ => return Err(invalid_token("malformed token")),
+            };
+
+        let header: Value = decode_json(header)?;
+        if header.get("alg").and_then(Value::as_str) != Some(self.key.algorithm().name()) {
+            return Err(invalid_token("unexpected algorithm"));
+        }
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| invalid_token("malformed signature"))?;
+        let message = &token[..token.rfind('.').unwrap()];
+        if !self.key.verify(message.as_bytes(), &signature) {
+            return Err(invalid_token("invalid signature"));
+        }
+
+        let claims = match decode_json(claims)? {
+            Value::Object(claims) => Claims(claims),
+            _ => return Err(invalid_token("claims are not an object")),
+        };
+        for name in &self.required_claims {
+            if claims.get(name).is_none() {
+                return Err(invalid_token(&format!("missing {} claim", name)));
+            }
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(Error::other)?
+            .as_secs_f64();
+        let leeway = self.leeway.as_secs_f64();
+        if let Some(exp) = claims.get("exp") {
+            match exp.as_f64() {
+                Some(exp) if now <= exp + leeway => {}
+                Some(_) => return Err(invalid_token("token expired")),
+                None => return Err(invalid_token("invalid exp claim")),
+            }
+        }
+        if let Some(nbf) = claims.get("nbf") {
+            match nbf.as_f64() {
+                Some(nbf) if now + leeway >= nbf => {}
+                Some(_) => return Err(invalid_token("token not valid yet")),
+                None => return Err(invalid_token("invalid nbf claim")),
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return Err(invalid_token("unexpected issuer"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                return Err(invalid_token("unexpected audience"));
+            }
+        }
+        Ok(claims)
+    }
+}
+
+fn decode_json(part: &str) -> Result<Value> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| invalid_token("malformed token"))?;
+    serde_json::from_slice(&bytes).map_err(|_| invalid_token("malformed token"))
+}
+
+fn invalid_token(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn invalid_key(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("invalid key: {}", message))
+}
+
+// The RSAPublicKey inside a SubjectPublicKeyInfo, which is what ring verifies with.
+fn spki_rsa_public_key(der: &[u8]) -> Option<Vec<u8>> {
+    let rsa_encryption = ObjectIdentifier::from_slice(&[1, 2, 840, 113549, 1, 1, 1]);
+    let (algorithm, (key, unused_bits)) = yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let algorithm = reader.next().read_sequence(|reader| {
+                let algorithm = reader.next().read_oid()?;
+                reader.read_optional(|reader| reader.read_null())?;
+                Ok(algorithm)
+            })?;
+            let key = reader.next().read_bitvec_bytes()?;
+            Ok((algorithm, key))
+        })
+    })
+    .ok()?;
+    if algorithm != rsa_encryption || unused_bits % 8 != 0 || !is_rsa_public_key(&key) {
+        return None;
+    }
+    Some(key)
+}
+
+// Whether der is a PKCS#1 RSAPublicKey, the modulus and public exponent of the key.
+fn is_rsa_public_key(der: &[u8]) -> bool {
+    yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            reader.next().read_bigint_bytes()?;
+            reader.next().read_bigint_bytes()?;
+            Ok(())
+        })
+    })
+    .is_ok()
+}
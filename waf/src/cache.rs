@@ -0,0 +1,220 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::date;
+use crate::http::{Headers, METHOD_GET, METHOD_HEAD};
+use crate::response::Response;
+
+// Builds a Cache-Control response header:
+//
+//     let cache = CacheControl::new().public().max_age(Duration::from_secs(3600));
+//     response.set_header("Cache-Control", &cache.to_string());
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheControl {
+    directives: Vec<String>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        CacheControl {
+            directives: Vec::new(),
+        }
+    }
+
+    pub fn public(self) -> Self {
+        self.directive("public")
+    }
+
+    pub fn private(self) -> Self {
+        self.directive("private")
+    }
+
+    pub fn no_cache(self) -> Self {
+        self.directive("no-cache")
+    }
+
+    pub fn no_store(self) -> Self {
+        self.directive("no-store")
+    }
+
+    pub fn no_transform(self) -> Self {
+        self.directive("no-transform")
+    }
+
+    pub fn must_revalidate(self) -> Self {
+        self.directive("must-revalidate")
+    }
+
+    pub fn proxy_revalidate(self) -> Self {
+        self.directive("proxy-revalidate")
+    }
+
+    pub fn immutable(self) -> Self {
+        self.directive("immutable")
+    }
+
+    pub fn max_age(self, age: Duration) -> Self {
+        self.seconds("max-age", age)
+    }
+
+    pub fn s_maxage(self, age: Duration) -> Self {
+        self.seconds("s-maxage", age)
+    }
+
+    pub fn stale_while_revalidate(self, age: Duration) -> Self {
+        self.seconds("stale-while-revalidate", age)
+    }
+
+    pub fn stale_if_error(self, age: Duration) -> Self {
+        self.seconds("stale-if-error", age)
+    }
+
+    // Any other directive, written as given, e.g. "max-stale=60".
+    pub fn directive(mut self, directive: &str) -> Self {
+        self.directives.push(directive.to_string());
+        self
+    }
+
+    fn seconds(self, name: &str, age: Duration) -> Self {
+        self.directive(&format!("{}={}", name, age.as_secs()))
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.directives.join(", "))
+    }
+}
+
+// The validators of a representation, against which conditional requests are evaluated:
+//
+//     let validators = Validators::new().with_etag("\"v1\"");
+//     if let Some(status) = validators.evaluate(&conn.method, &conn.headers) {
+//         return Ok(validators.response(status));
+//     }
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Validators {
+    etag: Option<String>,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    pub fn new() -> Self {
+        Validators {
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    // A quoted entity tag, optionally prefixed with W/ if it is weak.
+    pub fn with_etag(mut self, etag: &str) -> Self {
+        self.etag = Some(etag.to_string());
+        self
+    }
+
+    pub fn with_last_modified(mut self, last_modified: DateTime<Utc>) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    pub fn last_modified(&self) -> Option<&DateTime<Utc>> {
+        self.last_modified.as_ref()
+    }
+
+    // Evaluates If-Match, If-Unmodified-Since, If-None-Match and If-Modified-Since in the order
+    // of RFC 9110 13.2.2. Returns 304 or 412 if the request shouldn't be served as usual.
+    pub fn evaluate(&self, method: &str, headers: &Headers) -> Option<u16> {
+        let is_get = method == METHOD_GET || method == METHOD_HEAD;
+
+        if let Some(if_match) = headers.get("If-Match") {
+            if !self.etag_matches(if_match, false) {
+                return Some(412);
+            }
+        } else if let Some(since) = headers.get("If-Unmodified-Since").and_then(date::parse) {
+            if self.modified_since(&since) {
+                return Some(412);
+            }
+        }
+
+        if let Some(if_none_match) = headers.get("If-None-Match") {
+            if self.etag_matches(if_none_match, true) {
+                return Some(if is_get { 304 } else { 412 });
+            }
+        } else if is_get {
+            if let Some(since) = headers.get("If-Modified-Since").and_then(date::parse) {
+                if self.last_modified.is_some() && !self.modified_since(&since) {
+                    return Some(304);
+                }
+            }
+        }
+        None
+    }
+
+    // Whether a Range request may be served partially. If-Range needs a strong match
+    // (RFC 9110 13.1.5); without the header it always may.
+    pub fn if_range(&self, headers: &Headers) -> bool {
+        let value = match headers.get("If-Range") {
+            Some(value) => value.trim(),
+            None => return true,
+        };
+        if value.starts_with('"') {
+            return self.etag.as_deref() == Some(value);
+        }
+        match (date::parse(value), &self.last_modified) {
+            (Some(date), Some(last_modified)) => date.timestamp() == last_modified.timestamp(),
+            _ => false,
+        }
+    }
+
+    // Sets the ETag and Last-Modified headers of response.
+    pub fn set_headers(&self, response: &mut Response) {
+        if let Some(etag) = &self.etag {
+            response.set_header("ETag", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            response.set_header("Last-Modified", &date::format(last_modified));
+        }
+    }
+
+    // The response to a request that evaluate() returned status for. A 304 keeps the
+    // validators.
+    pub fn response(&self, status: u16) -> Response {
+        let mut response = Response::new(status);
+        if status == 304 {
+            self.set_headers(&mut response);
+        }
+        response
+    }
+
+    fn etag_matches(&self, list: &str, weak: bool) -> bool {
+        // The representation evaluated against exists, so it matches "*"
+        if list.trim() == "*" {
+            return true;
+        }
+        let etag = match &self.etag {
+            Some(etag) => etag.as_str(),
+            None => return false,
+        };
+        list.split(',').map(|tag| tag.trim()).any(|tag| {
+            if weak {
+                tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+            } else {
+                !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+            }
+        })
+    }
+
+    // Dates have a resolution of one second, so finer differences don't count.
+    fn modified_since(&self, since: &DateTime<Utc>) -> bool {
+        match &self.last_modified {
+            Some(last_modified) => last_modified.timestamp() > since.timestamp(),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{Read, Result, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::http::Connection;
+use crate::middleware::{Middleware, Next};
+use crate::response::{Body, Response};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    // In order of preference when a client accepts several with the same weight.
+    const ALL: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    // Picks the encoding to use from an Accept-Encoding header, None meaning identity.
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut weights = Vec::new();
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let coding = params.next().unwrap_or("").to_ascii_lowercase();
+            if coding.is_empty() {
+                continue;
+            }
+            let mut weight = 1.0;
+            for param in params {
+                if let Some((key, value)) = param.split_once('=') {
+                    if key.trim().eq_ignore_ascii_case("q") {
+                        weight = value.trim().parse().unwrap_or(0.0);
+                    }
+                }
+            }
+            weights.push((coding, weight));
+        }
+        let weight_of = |name: &str| {
+            weights
+                .iter()
+                .find(|(coding, _)| coding == name || (name == "gzip" && coding == "x-gzip"))
+                .or_else(|| weights.iter().find(|(coding, _)| coding == "*"))
+                .map_or(0.0, |(_, weight)| *weight)
+        };
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for &encoding in Encoding::ALL {
+            let weight = weight_of(encoding.name());
+            if weight > best.map_or(0.0, |(_, best)| best) {
+                best = Some((encoding, weight));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn encode_bytes(self, bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut encoder = self.writer(&mut output, level);
+        encoder.write_all(bytes)?;
+        encoder.finish()?;
+        Ok(output)
+    }
+
+    fn reader(self, reader: Box<dyn Read + Send>, level: u32) -> Box<dyn Read + Send> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                4096,
+                level.min(11),
+                22,
+            )),
+            Encoding::Gzip => Box::new(flate2::read::GzEncoder::new(
+                reader,
+                flate2::Compression::new(level.min(9)),
+            )),
+            Encoding::Deflate => Box::new(flate2::read::ZlibEncoder::new(
+                reader,
+                flate2::Compression::new(level.min(9)),
+            )),
+        }
+    }
+
+    fn writer<W: Write>(self, writer: W, level: u32) -> Encoder<W> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                writer,
+                4096,
+                level.min(11),
+                22,
+            ))),
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(
+                writer,
+                flate2::Compression::new(level.min(9)),
+            )),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(
+                writer,
+                flate2::Compression::new(level.min(9)),
+            )),
+        }
+    }
+}
+
+enum Encoder<W: Write> {
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<W>>),
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn finish(self) -> Result<()> {
+        let mut writer = match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.into_inner(),
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Deflate(encoder) => encoder.finish()?,
+        };
+        writer.flush()
+    }
+}
+
+// Flushing pushes out everything written so far, so that streamed bodies such as event
+// streams aren't held back by the encoder.
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Deflate(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// Middleware compressing response bodies with the best encoding the client accepts:
+//
+//     router.add_middleware(Compression::new().with_min_size(512));
+//
+// Only responses with a compressible Content-Type are compressed, and those get
+// "Vary: Accept-Encoding" whether or not this request was compressed. Bodies of known size
+// below the minimum are sent as they are; streamed bodies are compressed on the fly and sent
+// chunked.
+pub struct Compression {
+    min_size: u64,
+    level: u32,
+    content_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression {
+            min_size: 1024,
+            level: 6,
+            content_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/xhtml+xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|content_type| content_type.to_string())
+            .collect(),
+        }
+    }
+
+    pub fn with_min_size(mut self, size: u64) -> Self {
+        self.min_size = size;
+        self
+    }
+
+    // 0 to 9 for gzip and deflate, up to 11 for brotli.
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    // Media types to compress, replacing the defaults. "type/*" matches a whole type, and
+    // parameters such as charset are ignored.
+    pub fn with_content_types(mut self, content_types: &[&str]) -> Self {
+        self.content_types = content_types
+            .iter()
+            .map(|content_type| content_type.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    fn is_compressible(&self, response: &Response) -> bool {
+        let status = response.status();
+        if status / 100 == 1 || status == 204 || status == 206 || status == 304 {
+            return false;
+        }
+        let headers = response.headers();
+        if headers.contains("Content-Encoding")
+            || headers.contains("Content-Range")
+            || headers.has_token("Cache-Control", "no-transform")
+        {
+            return false;
+        }
+        let content_type = match headers.get("Content-Type") {
+            Some(content_type) => content_type,
+            None => return false,
+        };
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => media_type.starts_with(prefix),
+                None => media_type == *pattern,
+            })
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let accept_encoding = conn.header("Accept-Encoding").map(str::to_string);
+        let mut response = next.run(conn)?;
+        if !self.is_compressible(&response) {
+            return Ok(response);
+        }
+        response.add_vary("Accept-Encoding");
+
+        let encoding = match accept_encoding.as_deref().and_then(Encoding::negotiate) {
+            Some(encoding) => encoding,
+            None => return Ok(response),
+        };
+        if response
+            .body()
+            .len()
+            .is_some_and(|len| len == 0 || len < self.min_size)
+        {
+            return Ok(response);
+        }
+
+        let level = self.level;
+        let body = match response.take_body() {
+            Body::Empty => Body::Empty,
+            Body::Bytes(bytes) => Body::Bytes(encoding.encode_bytes(&bytes, level)?),
+            Body::File(file) => Body::Stream(encoding.reader(Box::new(file), level)),
+            Body::Stream(reader) => Body::Stream(encoding.reader(reader, level)),
+            Body::Writer(write_body) => Body::Writer(Box::new(move |writer| {
+                let mut encoder = encoding.writer(writer, level);
+                write_body(&mut encoder)?;
+                encoder.finish()
+            })),
+        };
+        response.set_body(body);
+        response.set_header("Content-Encoding", encoding.name());
+        response.remove_header("Content-Length");
+        response.remove_header("Accept-Ranges");
+        // The compressed bytes differ from the identity ones, so a strong validator no longer
+        // holds.
+        if let Some(etag) = response.header("ETag") {
+            if !etag.starts_with("W/") {
+                let etag = format!("W/{}", etag);
+                response.set_header("ETag", &etag);
+            }
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,412 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{self, HKDF_SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::date;
+use crate::http::{is_token, Connection, Headers};
+use crate::response::Response;
+use crate::route::percent_decode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+// A cookie to send in Set-Cookie:
+//
+//     response.set_cookie(&Cookie::new("theme", "dark").with_max_age(Duration::from_secs(86400)));
+//
+// Values may hold any text; bytes that can't appear in a cookie are percent-encoded, and
+// decoded again when read from a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<DateTime<Utc>>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    // Panics unless the name is a token.
+    pub fn new(name: &str, value: &str) -> Self {
+        assert!(is_token(name), "invalid cookie name: {:?}", name);
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // A cookie telling the client to drop the one with this name. Its path and domain must be
+    // those the cookie was set with.
+    pub fn removal(name: &str) -> Self {
+        let mut cookie = Cookie::new(name, "");
+        cookie.max_age = Some(Duration::from_secs(0));
+        cookie.expires = Some(UNIX_EPOCH.into());
+        cookie
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    pub fn with_value(mut self, value: &str) -> Self {
+        self.value = value.to_string();
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_expires(mut self, expires: DateTime<Utc>) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    // SameSite=None is only accepted by browsers on secure cookies, so it implies Secure.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+// The Set-Cookie header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, encode_value(&self.value))?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", encode_value(path))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", encode_value(domain))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = &self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Parses a Cookie header into name-value pairs, skipping malformed ones.
+pub fn parse_cookie_header(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if !is_token(name) {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_string(), percent_decode(value)))
+        })
+        .collect()
+}
+
+// The cookies of a request along with the ones to set in its response.
+//
+//     let mut jar = conn.cookies();
+//     let visits: u32 = jar.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0);
+//     jar.add(Cookie::new("visits", &(visits + 1).to_string()));
+//     jar.apply(&mut response);
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+    changes: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_headers(headers: &Headers) -> Self {
+        CookieJar {
+            cookies: headers
+                .get_all("Cookie")
+                .flat_map(parse_cookie_header)
+                .collect(),
+            changes: Vec::new(),
+        }
+    }
+
+    // The value added to the jar last, or else the first sent by the client.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self.changes.iter().rev().find(|cookie| cookie.name == name) {
+            Some(cookie) if cookie.max_age == Some(Duration::from_secs(0)) => None,
+            Some(cookie) => Some(&cookie.value),
+            None => self
+                .cookies
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+
+    // The cookies sent by the client.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn add(&mut self, cookie: Cookie) {
+        self.changes.push(cookie);
+    }
+
+    // Removes the cookie set with the same name, path and domain as this one.
+    pub fn remove(&mut self, cookie: Cookie) {
+        let mut removal = Cookie::removal(&cookie.name);
+        removal.path = cookie.path;
+        removal.domain = cookie.domain;
+        self.changes.push(removal);
+    }
+
+    // Adds a cookie the client can read but not forge.
+    pub fn add_signed(&mut self, cookie: Cookie, key: &Key) {
+        let value = key.sign(&cookie.name, &cookie.value);
+        self.add(cookie.with_value(&value));
+    }
+
+    // The value of a cookie added by add_signed(), None if it was tampered with.
+    pub fn get_signed(&self, name: &str, key: &Key) -> Option<String> {
+        key.verify(name, self.get(name)?)
+    }
+
+    // Adds a cookie the client can neither read nor forge.
+    pub fn add_private(&mut self, cookie: Cookie, key: &Key) {
+        let value = key.encrypt(&cookie.name, &cookie.value);
+        self.add(cookie.with_value(&value));
+    }
+
+    // The value of a cookie added by add_private(), None if it was tampered with.
+    pub fn get_private(&self, name: &str, key: &Key) -> Option<String> {
+        key.decrypt(name, self.get(name)?)
+    }
+
+    pub fn changes(&self) -> &[Cookie] {
+        &self.changes
+    }
+
+    // Adds a Set-Cookie header for each change.
+    pub fn apply(&self, response: &mut Response) {
+        for cookie in &self.changes {
+            response.set_cookie(cookie);
+        }
+    }
+}
+
+// Secret for signed and private cookies. Use the same key across restarts and servers, or
+// their cookies become invalid.
+pub struct Key {
+    signing: hmac::Key,
+    encryption: LessSafeKey,
+}
+
+impl Key {
+    // Derives the keys from a secret of at least 32 bytes.
+    pub fn new(secret: &[u8]) -> Result<Self> {
+        if secret.len() < 32 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "cookie key secret must be at least 32 bytes",
+            ));
+        }
+        let prk = hkdf::Salt::new(HKDF_SHA256, b"waf cookie key").extract(secret);
+        let signing = prk
+            .expand(&[b"signing"], hmac::HMAC_SHA256)
+            .map_err(|_| Error::other("cannot derive the signing key"))?;
+        let encryption = prk
+            .expand(&[b"encryption"], &AES_256_GCM)
+            .map_err(|_| Error::other("cannot derive the encryption key"))?;
+        Ok(Key {
+            signing: hmac::Key::from(signing),
+            encryption: LessSafeKey::new(UnboundKey::from(encryption)),
+        })
+    }
+
+    // A random key, for cookies that needn't outlive the process.
+    pub fn generate() -> Result<Self> {
+        let mut secret = [0; 32];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| Error::other("no random source"))?;
+        Key::new(&secret)
+    }
+
+    // The name is covered too, so that a value can't be moved to another cookie.
+    fn sign(&self, name: &str, value: &str) -> String {
+        let tag = hmac::sign(&self.signing, format!("{}={}", name, value).as_bytes());
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()), value)
+    }
+
+    fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (tag, value) = signed.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        let message = format!("{}={}", name, value);
+        hmac::verify(&self.signing, message.as_bytes(), &tag).ok()?;
+        Some(value.to_string())
+    }
+
+    fn encrypt(&self, name: &str, value: &str) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("no random source");
+        let mut data = value.as_bytes().to_vec();
+        self.encryption
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut data,
+            )
+            .expect("cookie value too large to encrypt");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&data);
+        URL_SAFE_NO_PAD.encode(sealed)
+    }
+
+    fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN + aead::MAX_TAG_LEN {
+            return None;
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut data = data.to_vec();
+        let value = self
+            .encryption
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut data)
+            .ok()?;
+        String::from_utf8(value.to_vec()).ok()
+    }
+}
+
+impl Connection {
+    // The first value of a cookie sent with the request.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(parse_cookie_header)
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::from_headers(&self.headers)
+    }
+}
+
+impl Response {
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.append_header("Set-Cookie", &cookie.to_string());
+    }
+
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Self {
+        self.set_cookie(cookie);
+        self
+    }
+}
+
+// Leaves the cookie-octets of RFC 6265 as they are, except '%'. percent_decode() undoes it.
+fn encode_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            0x21 | 0x23..=0x24 | 0x26..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
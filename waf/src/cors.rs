@@ -0,0 +1,190 @@
+use std::io::Result;
+use std::time::Duration;
+
+use crate::http::{Connection, METHOD_OPTIONS};
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+
+// Middleware for cross-origin requests:
+//
+//     router.add_middleware(
+//         Cors::new()
+//             .with_origins(&["https://app.example.com"])
+//             .with_headers(&["Content-Type", "Authorization"])
+//             .with_credentials(true),
+//     );
+//
+// Preflight requests go through the router like any other, so routes without an OPTIONS
+// handler are answered by the automatic one, and the methods allowed default to those of the
+// route. Requests from other origins get no CORS headers, which makes browsers reject them.
+pub struct Cors {
+    origins: Option<Vec<String>>,
+    methods: Option<Vec<String>>,
+    headers: Option<Vec<String>>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    // Allows no origin until configured.
+    pub fn new() -> Self {
+        Cors {
+            origins: Some(Vec::new()),
+            methods: None,
+            headers: Some(Vec::new()),
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    // Origins such as "https://example.com", compared case-insensitively.
+    pub fn with_origins(mut self, origins: &[&str]) -> Self {
+        self.origins = Some(origins.iter().map(|origin| origin.to_string()).collect());
+        self
+    }
+
+    pub fn with_any_origin(mut self) -> Self {
+        self.origins = None;
+        self
+    }
+
+    // Restricts the methods of preflighted requests, which are otherwise those of the route.
+    pub fn with_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = Some(methods.iter().map(|method| method.to_string()).collect());
+        self
+    }
+
+    // Request headers allowed besides the CORS-safelisted ones.
+    pub fn with_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = Some(headers.iter().map(|header| header.to_string()).collect());
+        self
+    }
+
+    // Allows whatever headers a preflight request asks for.
+    pub fn with_any_header(mut self) -> Self {
+        self.headers = None;
+        self
+    }
+
+    // Response headers scripts may read besides the CORS-safelisted ones.
+    pub fn with_exposed_headers(mut self, headers: &[&str]) -> Self {
+        self.exposed_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    // Whether requests may carry cookies and credentials. The origin is then always echoed
+    // instead of "*".
+    pub fn with_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    // How long browsers may cache a preflight response.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn is_allowed_origin(&self, origin: &str) -> bool {
+        match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            None => true,
+        }
+    }
+
+    // Whether responses are the same whatever the origin.
+    fn is_wildcard(&self) -> bool {
+        self.origins.is_none() && !self.credentials
+    }
+
+    fn allow_origin(&self, response: &mut Response, origin: &str) {
+        if self.is_wildcard() {
+            response.set_header("Access-Control-Allow-Origin", "*");
+        } else {
+            response.set_header("Access-Control-Allow-Origin", origin);
+        }
+        if self.credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, response: &mut Response, origin: &str, method: &str, headers: &str) {
+        response.add_vary("Access-Control-Request-Method");
+        response.add_vary("Access-Control-Request-Headers");
+        if response.status() / 100 != 2 || !self.is_allowed_origin(origin) {
+            return;
+        }
+        let methods = match &self.methods {
+            Some(methods) => methods.join(", "),
+            None => response.header("Allow").unwrap_or("").to_string(),
+        };
+        if !methods
+            .split(',')
+            .any(|allowed| allowed.trim().eq_ignore_ascii_case(method))
+        {
+            return;
+        }
+
+        self.allow_origin(response, origin);
+        response.set_header("Access-Control-Allow-Methods", &methods);
+        let headers = match &self.headers {
+            Some(allowed) => allowed.join(", "),
+            None => headers.to_string(),
+        };
+        if !headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", &headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let origin = conn.header("Origin").map(str::to_string);
+        let request_method = conn
+            .header("Access-Control-Request-Method")
+            .map(str::to_string);
+        let request_headers = conn
+            .header("Access-Control-Request-Headers")
+            .unwrap_or("")
+            .to_string();
+        let is_preflight = conn.method == METHOD_OPTIONS;
+
+        let mut response = next.run(conn)?;
+        if !self.is_wildcard() {
+            response.add_vary("Origin");
+        }
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return Ok(response),
+        };
+        match request_method {
+            Some(method) if is_preflight => {
+                self.preflight(&mut response, &origin, &method, &request_headers)
+            }
+            _ if self.is_allowed_origin(&origin) => {
+                self.allow_origin(&mut response, &origin);
+                if !self.exposed_headers.is_empty() {
+                    response.set_header(
+                        "Access-Control-Expose-Headers",
+                        &self.exposed_headers.join(", "),
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+const RFC_850: &str = "%A, %d-%b-%Y %H:%M:%S GMT";
+const ASCTIME: &str = "%a %b %e %H:%M:%S %Y";
+
+// Formats date as an IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT", the only format HTTP
+// senders may generate (RFC 9110 5.6.7).
+pub fn format(date: &DateTime<Utc>) -> String {
+    date.format(IMF_FIXDATE).to_string()
+}
+
+pub fn now() -> String {
+    format(&Utc::now())
+}
+
+// Parses any of the three formats recipients must accept:
+//
+//     Sun, 06 Nov 1994 08:49:37 GMT   IMF-fixdate
+//     Sunday, 06-Nov-94 08:49:37 GMT  obsolete RFC 850 format
+//     Sun Nov  6 08:49:37 1994        ANSI C's asctime() format
+pub fn parse(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    let date = NaiveDateTime::parse_from_str(s, IMF_FIXDATE)
+        .or_else(|_| NaiveDateTime::parse_from_str(s, ASCTIME))
+        .ok()
+        .or_else(|| parse_rfc_850(s))?;
+    Some(Utc.from_utc_datetime(&date))
+}
+
+// A two-digit year that would be more than 50 years in the future is taken to be in the past
+// century (RFC 9110 5.6.7).
+fn parse_rfc_850(s: &str) -> Option<NaiveDateTime> {
+    let (weekday, rest) = s.split_once(", ")?;
+    let (day_month, year, rest) = (rest.get(..7)?, rest.get(7..9)?, rest.get(9..)?);
+    if !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year: i32 = year.parse().ok()?;
+
+    let this_year = Utc::now().year();
+    let mut full_year = this_year - this_year % 100 + year;
+    if full_year > this_year + 50 {
+        full_year -= 100;
+    }
+    let expanded = format!("{}, {}{}{}", weekday, day_month, full_year, rest);
+    NaiveDateTime::parse_from_str(&expanded, RFC_850).ok()
+}
@@ -0,0 +1,287 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::http::{Connection, Headers, HttpError};
+use crate::response::Response;
+use crate::route::Params;
+use crate::{Handler, Router};
+
+// Values shared by all handlers, one per type, registered with Router::add_state().
+#[derive(Clone, Default)]
+pub(crate) struct AppState(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl AppState {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Router {
+    // Shares a value, such as a database pool or a configuration, with every handler:
+    //
+    //     router.add_state(Config::load()?);
+    //     router.add_handler(METHOD_GET, "/", handler(|State(config): State<Config>| { ... }));
+    //
+    // Values are shared as they are, so anything mutable needs a Mutex or atomics inside.
+    pub fn add_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        Arc::make_mut(&mut self.state)
+            .0
+            .insert(TypeId::of::<T>(), Arc::new(state));
+        self
+    }
+}
+
+impl Connection {
+    // A value registered with Router::add_state().
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let state = self.extensions().get::<Arc<AppState>>()?;
+        let value = state.0.get(&TypeId::of::<T>())?.clone();
+        value.downcast().ok()
+    }
+}
+
+// Something a handler can take as an argument, built from the request. Fails with
+// ErrorKind::NotFound when the request doesn't have the value at all, so that Option<T> can
+// tell that apart from a value that is there but invalid.
+pub trait FromRequest: Sized {
+    fn from_request(conn: &mut Connection) -> Result<Self>;
+}
+
+// Functions whose arguments are all FromRequest, up to 8 of them.
+pub trait ExtractorFn<Args>: Send + Sync + 'static {
+    fn call(&self, conn: &mut Connection) -> Result<Response>;
+}
+
+macro_rules! impl_extractor_fn {
+    ($($ty:ident $arg:ident),*) => {
+        impl<F, $($ty,)*> ExtractorFn<($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> Result<Response> + Send + Sync + 'static,
+            $($ty: FromRequest,)*
+        {
+            #[allow(unused_variables)]
+            fn call(&self, conn: &mut Connection) -> Result<Response> {
+                $(let $arg = $ty::from_request(conn)?;)*
+                (self)($($arg),*)
+            }
+        }
+    };
+}
+
+impl_extractor_fn!();
+impl_extractor_fn!(A a);
+impl_extractor_fn!(A a, B b);
+impl_extractor_fn!(A a, B b, C c);
+impl_extractor_fn!(A a, B b, C c, D d);
+impl_extractor_fn!(A a, B b, C c, D d, E e);
+impl_extractor_fn!(A a, B b, C c, D d, E e, G g);
+impl_extractor_fn!(A a, B b, C c, D d, E e, G g, H h);
+impl_extractor_fn!(A a, B b, C c, D d, E e, G g, H h, I i);
+
+// Turns a function taking extractors into a Handler:
+//
+//     fn update_item(Path(id): Path<u64>, Json(item): Json<Item>) -> io::Result<Response> { ... }
+//
+//     router.add_handler(METHOD_PUT, "/items/:id<uint>", handler(update_item));
+//
+// Arguments are extracted in order, and the first that fails answers the request instead of
+// the function. Extractors that read the body, such as Json, must come last.
+pub fn handler<Args, F: ExtractorFn<Args>>(f: F) -> Extract<F, Args> {
+    Extract {
+        f,
+        args: PhantomData,
+    }
+}
+
+pub struct Extract<F, Args> {
+    f: F,
+    args: PhantomData<fn() -> Args>,
+}
+
+impl<F: ExtractorFn<Args>, Args: 'static> Handler for Extract<F, Args> {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        self.f.call(conn)
+    }
+}
+
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        conn.state().map(State).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no state of type {}", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// Path parameters: a single value, a tuple of all of them in order, or any type implementing
+// FromParams.
+pub struct Path<T>(pub T);
+
+pub trait FromParams: Sized {
+    fn from_params(params: &Params) -> Result<Self>;
+}
+
+impl<T: FromParams> FromRequest for Path<T> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        T::from_params(&conn.params).map(Path)
+    }
+}
+
+macro_rules! impl_from_params_value {
+    ($($ty:ty),*) => {
+        $(
+            impl FromParams for $ty {
+                fn from_params(params: &Params) -> Result<Self> {
+                    match params.iter().collect::<Vec<_>>().as_slice() {
+                        [(_, value)] => parse_param(value),
+                        _ => Err(param_count_error(1, params.len())),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_params_value!(
+    String, bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+macro_rules! impl_from_params_tuple {
+    ($len:expr, $($ty:ident),*) => {
+        impl<$($ty: FromStr,)*> FromParams for ($($ty,)*) {
+            fn from_params(params: &Params) -> Result<Self> {
+                if params.len() != $len {
+                    return Err(param_count_error($len, params.len()));
+                }
+                let mut values = params.iter().map(|(_, value)| value);
+                Ok(($(parse_param::<$ty>(values.next().unwrap())?,)*))
+            }
+        }
+    };
+}
+
+impl_from_params_tuple!(1, A);
+impl_from_params_tuple!(2, A, B);
+impl_from_params_tuple!(3, A, B, C);
+impl_from_params_tuple!(4, A, B, C, D);
+
+impl FromRequest for Params {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        Ok(conn.params.clone())
+    }
+}
+
+fn parse_param<T: FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| HttpError::new(400, &format!("invalid path parameter: {}", value)).into())
+}
+
+fn param_count_error(expected: usize, found: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("expected {} path parameters, found {}", expected, found),
+    )
+}
+
+// The query string deserialized into a struct, whose missing fields must be Options.
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        let query = conn.query.as_deref().unwrap_or("");
+        serde_urlencoded::from_str(query)
+            .map(Query)
+            .map_err(|e| HttpError::new(400, &format!("invalid query: {}", e)).into())
+    }
+}
+
+// A JSON request body, requiring a JSON Content-Type. Also builds JSON responses:
+//
+//     Json(&items).into_response()
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        let is_json = conn.header("Content-Type").is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or("").trim();
+            media_type.eq_ignore_ascii_case("application/json")
+                || media_type.to_ascii_lowercase().ends_with("+json")
+        });
+        if !is_json {
+            let e = HttpError::new(415, "expected a JSON body");
+            if conn.header("Content-Type").is_none() {
+                return Err(Error::new(ErrorKind::NotFound, e));
+            }
+            return Err(e.into());
+        }
+        let mut body = Vec::new();
+        conn.read_to_end(&mut body)?;
+        serde_json::from_slice(&body)
+            .map(Json)
+            .map_err(|e| HttpError::new(400, &format!("invalid JSON: {}", e)).into())
+    }
+}
+
+impl<T: Serialize> Json<T> {
+    pub fn into_response(self) -> Result<Response> {
+        let body = serde_json::to_string(&self.0).map_err(Error::other)?;
+        Ok(Response::json(body))
+    }
+}
+
+impl FromRequest for Headers {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        Ok(conn.headers.clone())
+    }
+}
+
+// The body as UTF-8 text.
+impl FromRequest for String {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        let mut body = Vec::new();
+        conn.read_to_end(&mut body)?;
+        String::from_utf8(body).map_err(|_| HttpError::new(400, "body is not UTF-8").into())
+    }
+}
+
+// The raw body.
+impl FromRequest for Vec<u8> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        let mut body = Vec::new();
+        conn.read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+// None when the request doesn't have the value, such as state that was never added or a
+// request without a body for Json. Invalid values and failed reads still fail.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(conn: &mut Connection) -> Result<Self> {
+        match T::from_request(conn) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -1,10 +1,154 @@
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
 use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use chrono::Utc;
+use crate::date;
+use crate::response::{Body, Response};
+use crate::route::Params;
+use crate::server::ShutdownSignal;
 
-pub type Headers = HashMap<String, String>;
+// Header fields in the order they were received. Names are case-insensitive and a name may
+// occur more than once.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    // Whether a comma-separated header like Connection contains token, ignoring case.
+    pub fn has_token(&self, key: &str, token: &str) -> bool {
+        self.get_all(key)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.remove(key);
+        self.append(key, value);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // Longer Request-Lines are answered with 414
+    pub max_request_line: usize,
+    // Total size of the header section, and the number of fields in it. Beyond either, 431
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    // Larger bodies are answered with 413. None for no limit
+    pub max_body_size: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_line: 8 * 1024,
+            max_header_size: 64 * 1024,
+            max_headers: 100,
+            max_body_size: Some(16 * 1024 * 1024),
+        }
+    }
+}
+
+// An error that should be answered with a specific status code. It travels inside an
+// io::Error, see error_status().
+#[derive(Debug)]
+pub struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: &str) -> Self {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.status,
+            reason_phrase(self.status),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<HttpError> for Error {
+    fn from(e: HttpError) -> Self {
+        Error::new(ErrorKind::InvalidData, e)
+    }
+}
+
+pub fn error_status(e: &Error) -> Option<u16> {
+    e.get_ref()
+        .and_then(|e| e.downcast_ref::<HttpError>())
+        .map(|e| e.status)
+}
+
+fn http_error(status: u16, message: String) -> Error {
+    HttpError { status, message }.into()
+}
 
 pub const METHOD_GET: &str = "GET";
 pub const METHOD_HEAD: &str = "HEAD";
@@ -16,80 +160,603 @@ pub const METHOD_OPTIONS: &str = "OPTIONS";
 pub const METHOD_TRACE: &str = "TRACE";
 pub const METHOD_PATCH: &str = "PATCH";
 
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: Headers,
+    pub params: Params,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key)
+    }
+}
+
+// Values attached to the current request by middleware, one per type, such as the session.
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the value of the same type that was replaced.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.0
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.0
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.0
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+pub trait Stream: Read + Write + Send + 'static {
+    // The socket underneath, which read timeouts are set on
+    fn socket(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn socket(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+struct SharedStream<S>(Arc<Mutex<S>>);
+
+impl<S> SharedStream<S> {
+    fn lock(&self) -> MutexGuard<'_, S> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<S> Clone for SharedStream<S> {
+    fn clone(&self) -> Self {
+        SharedStream(self.0.clone())
+    }
+}
+
+impl<S: Read> Read for SharedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<S: Write> Write for SharedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lock().flush()
+    }
+}
+
+// The read timeout of a socket, and a deadline that shortens it, e.g. while reading a request
+// head, so that a client trickling bytes in can't hold a connection indefinitely.
+#[derive(Default)]
+struct ReadTimeouts {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+struct TimedReader {
+    inner: Box<dyn Read + Send>,
+    socket: TcpStream,
+    timeouts: Arc<Mutex<ReadTimeouts>>,
+    applied: Option<Option<Duration>>,
+}
+
+impl Read for TimedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let timeout = {
+            let timeouts = self.timeouts.lock().unwrap_or_else(|e| e.into_inner());
+            match timeouts.deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(Error::new(ErrorKind::TimedOut, "read deadline expired"));
+                    }
+                    Some(timeouts.timeout.map_or(remaining, |t| t.min(remaining)))
+                }
+                None => timeouts.timeout,
+            }
+        };
+        // Only a change of timeout costs a system call.
+        if self.applied != Some(timeout) {
+            self.socket.set_read_timeout(timeout)?;
+            self.applied = Some(timeout);
+        }
+        self.inner.read(buf)
+    }
+}
+
+// An in-memory writer whose contents stay reachable after it is handed to a Connection.
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
 pub struct Connection {
-    reader: BufReader<TcpStream>,
-    writer: BufWriter<TcpStream>,
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: BufWriter<Box<dyn Write + Send>>,
+    socket: Option<TcpStream>,
+    peer_addr: Option<SocketAddr>,
+    secure: bool,
+    read_timeouts: Arc<Mutex<ReadTimeouts>>,
+    header_timeout: Option<Duration>,
+    shutdown: ShutdownSignal,
+    request_id: String,
     pub method: String,
     pub path: String,
+    pub query: Option<String>,
     pub version: String,
+    pub headers: Headers,
+    // How many headers read_header() has returned
+    headers_read: usize,
+    pub params: Params,
+    extensions: Extensions,
+    limits: Limits,
+    body: BodyReader,
+    keep_alive: bool,
+    status: Option<(u16, String)>,
+    response_headers: Headers,
+    header_finished: bool,
 }
 
 impl Connection {
+    // No request is read until next_request() is called.
     pub fn new(stream: TcpStream) -> Result<Self> {
-        let mut reader = BufReader::new(stream.try_clone()?);
-        let writer = BufWriter::new(stream.try_clone()?);
-
-        let mut line = "".to_string();
-        reader.read_line(&mut line)?;
-        let (method, path, version) = parse_request_line(&line)?;
-
-        Ok(Connection {
-            reader,
-            writer,
-            method,
-            path,
-            version,
-        })
+        let reader = Box::new(stream.try_clone()?);
+        let writer = Box::new(stream.try_clone()?);
+        Ok(Connection::open(reader, writer, Some(stream)))
     }
 
-    pub fn read_headers(&mut self) -> Result<HashMap<String, String>> {
-        let mut headers = HashMap::new();
-        while let Some((key, value)) = self.read_header()? {
-            headers.insert(key, value);
+    // A connection over a stream that can't be split into a reading and a writing half. Reads
+    // and writes take turns on it.
+    pub fn from_stream<S: Stream>(stream: S) -> Result<Self> {
+        let socket = stream.socket().map(TcpStream::try_clone).transpose()?;
+        let stream = SharedStream(Arc::new(Mutex::new(stream)));
+        Ok(Connection::open(
+            Box::new(stream.clone()),
+            Box::new(stream),
+            socket,
+        ))
+    }
+
+    // A connection over arbitrary streams, e.g. in-memory buffers. Timeouts are not supported.
+    pub fn from_io<R, W>(reader: R, writer: W) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        Connection::open(Box::new(reader), Box::new(writer), None)
+    }
+
+    pub(crate) fn open(
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+        socket: Option<TcpStream>,
+    ) -> Self {
+        let read_timeouts = Arc::new(Mutex::new(ReadTimeouts::default()));
+        let reader = match socket.as_ref().and_then(|s| s.try_clone().ok()) {
+            Some(socket) => Box::new(TimedReader {
+                inner: reader,
+                socket,
+                timeouts: read_timeouts.clone(),
+                applied: None,
+            }),
+            None => reader,
+        };
+        Connection {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+            peer_addr: socket.as_ref().and_then(|s| s.peer_addr().ok()),
+            secure: false,
+            socket,
+            read_timeouts,
+            header_timeout: None,
+            shutdown: ShutdownSignal::default(),
+            request_id: String::new(),
+            method: String::new(),
+            path: String::new(),
+            query: None,
+            version: "HTTP/1.1".to_string(),
+            headers: Headers::new(),
+            headers_read: 0,
+            params: Params::new(),
+            extensions: Extensions::new(),
+            limits: Limits::default(),
+            body: BodyReader::Length(0),
+            keep_alive: false,
+            status: None,
+            response_headers: Headers::new(),
+            header_finished: false,
         }
-        Ok(headers)
     }
 
-    pub fn read_header(&mut self) -> Result<Option<(String, String)>> {
-        let mut line = "".to_string();
-        self.reader.read_line(&mut line)?;
-        if &line == "\r\n" || &line == "\n" {
-            Ok(None)
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn set_peer_addr(&mut self, addr: SocketAddr) {
+        self.peer_addr = Some(addr);
+    }
+
+    // Whether the connection is over TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    // Tells when the server serving the connection starts shutting down. Never set for
+    // connections that aren't served by a server.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown.clone()
+    }
+
+    pub(crate) fn set_shutdown_signal(&mut self, shutdown: ShutdownSignal) {
+        self.shutdown = shutdown;
+    }
+
+    // Identifies the current request, e.g. in logs. Generated for each request unless a
+    // middleware such as logging::RequestId sets it.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    pub fn set_request_id(&mut self, id: &str) {
+        self.request_id = id.to_string();
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Reads the next request, skipping whatever the previous handler left unread of its body.
+    // Returns false if the client closed the connection instead. Malformed requests fail with
+    // an error carrying the status to answer with, see error_status().
+    pub fn next_request(&mut self) -> Result<bool> {
+        io::copy(&mut Read::by_ref(self), &mut io::sink())?;
+
+        self.params = Params::new();
+        self.extensions.clear();
+        self.keep_alive = false;
+        self.status = None;
+        self.response_headers = Headers::new();
+        self.header_finished = false;
+
+        if let Some(timeout) = self.header_timeout {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+            self.lock_read_timeouts().deadline = Some(Instant::now() + timeout);
+        }
+        let head = read_request_head(&mut self.reader, &self.limits);
+        let deadline = self.lock_read_timeouts().deadline.take();
+        let head = match head {
+            Err(e) if is_timeout(&e) && deadline.is_some_and(|d| Instant::now() >= d) => {
+                return Err(http_error(
+                    408,
+                    "request head not received in time".to_string(),
+                ));
+            }
+            head => head,
+        };
+        let head = match head? {
+            Some(head) => head,
+            None => return Ok(false),
+        };
+        self.method = head.method;
+        self.path = head.path;
+        self.query = head.query;
+        self.version = head.version;
+        self.headers = head.headers;
+        self.headers_read = 0;
+        self.body = head.body;
+        self.request_id = generate_request_id();
+        self.keep_alive = if self.headers.has_token("Connection", "close") {
+            false
         } else {
-            Ok(Some(parse_header(&line)?))
+            self.version == "HTTP/1.1" || self.headers.has_token("Connection", "keep-alive")
+        };
+        Ok(true)
+    }
+
+    // The headers as a map, keeping the last value of repeated ones. next_request() has already
+    // read them.
+    #[deprecated(note = "use the headers field, which keeps repeated headers")]
+    pub fn read_headers(&mut self) -> Result<HashMap<String, String>> {
+        Ok(self
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect())
+    }
+
+    // The next header of the request, None after the last one.
+    #[deprecated(note = "use the headers field")]
+    pub fn read_header(&mut self) -> Result<Option<(String, String)>> {
+        let header = self
+            .headers
+            .iter()
+            .nth(self.headers_read)
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        self.headers_read += header.is_some() as usize;
+        Ok(header)
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key)
+    }
+
+    // Cleared before every request.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    // Reads the whole body and takes a snapshot of the current request.
+    pub fn read_request(&mut self) -> Result<Request> {
+        let mut body = Vec::new();
+        self.read_to_end(&mut body)?;
+        Ok(Request {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            query: self.query.clone(),
+            version: self.version.clone(),
+            headers: self.headers.clone(),
+            params: self.params.clone(),
+            body,
+        })
+    }
+
+    // Trailer fields of a chunked request body, available once the body has been read to the end.
+    pub fn trailers(&self) -> Option<&Headers> {
+        match &self.body {
+            BodyReader::Chunked(decoder) => decoder.trailers(),
+            BodyReader::Length(_) | BodyReader::Upgraded => None,
         }
     }
 
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    // Limits each read from the socket, applied from the next read on.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.lock_read_timeouts().timeout = timeout;
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match &self.socket {
+            Some(socket) => socket.set_write_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    // Limits the time from the first byte of a request to the end of its head. Reading the head
+    // fails with ErrorKind::TimedOut past it.
+    pub fn set_header_timeout(&mut self, timeout: Option<Duration>) {
+        self.header_timeout = timeout;
+    }
+
+    fn lock_read_timeouts(&self) -> MutexGuard<'_, ReadTimeouts> {
+        self.read_timeouts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        self.status.as_ref().map(|(code, _)| *code)
+    }
+
+    pub fn is_header_finished(&self) -> bool {
+        self.header_finished
+    }
+
+    // The status line and headers are kept until finish_header() so that they can be written in
+    // any order.
     pub fn write_status(&mut self, code: u16, reason: &str) -> Result<()> {
-        self.writer
-            .write_fmt(format_args!("{} {} {}\r\n", self.version, code, reason))
+        self.status = Some((code, reason.to_string()));
+        Ok(())
     }
 
-    pub fn write_headers(&mut self, headers: &mut HashMap<String, String>) -> Result<()> {
-        for (key, value) in headers {
+    pub fn write_headers(&mut self, headers: &Headers) -> Result<()> {
+        for (key, value) in headers.iter() {
             self.write_header(key, value)?;
         }
         Ok(())
     }
 
     pub fn write_header(&mut self, key: &str, value: &str) -> Result<()> {
-        self.writer
-            .write_fmt(format_args!("{}: {}\r\n", key, value))
+        if self.header_finished {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("header {} written after finish_header", key),
+            ));
+        }
+        self.response_headers.append(key, value);
+        Ok(())
+    }
+
+    pub fn remove_header(&mut self, key: &str) {
+        self.response_headers.remove(key);
     }
 
     pub fn write_date_header(&mut self) -> Result<()> {
-        let date_str = &Utc::now().format("%a, %d %m %Y %H:%M:%S GMT").to_string();
-        self.write_header("Date", date_str)
+        self.write_header("Date", &date::now())
     }
 
     pub fn finish_header(&mut self) -> Result<()> {
-        self.writer.write_all("\r\n".as_bytes())
+        let (code, reason) = self
+            .status
+            .clone()
+            .unwrap_or_else(|| (200, "OK".to_string()));
+        self.writer
+            .write_fmt(format_args!("{} {} {}\r\n", self.version, code, reason))?;
+        for (key, value) in self.response_headers.iter() {
+            self.writer
+                .write_fmt(format_args!("{}: {}\r\n", key, value))?;
+        }
+        self.writer.write_all("\r\n".as_bytes())?;
+        self.header_finished = true;
+        Ok(())
+    }
+
+    // Sends response, normally 101 Switching Protocols, and hands the connection over to another
+    // protocol. Reading and writing the connection then transfers raw bytes, and the response
+    // the handler returns is discarded. Reads no longer time out, since the read timeout is meant
+    // for request bodies and the other protocol may stay quiet for long.
+    pub fn upgrade(&mut self, response: Response) -> Result<()> {
+        let (status, reason, headers, _) = response.into_parts();
+        self.status = Some((status, reason));
+        for (key, value) in headers.iter() {
+            self.response_headers.append(key, value);
+        }
+        self.finish_header()?;
+        self.writer.flush()?;
+        self.keep_alive = false;
+        self.body = BodyReader::Upgraded;
+        self.set_read_timeout(None)
+    }
+
+    pub fn is_upgraded(&self) -> bool {
+        matches!(self.body, BodyReader::Upgraded)
+    }
+
+    pub fn send(&mut self, response: Response) -> Result<()> {
+        if self.is_upgraded() {
+            return self.writer.flush();
+        }
+        let (status, reason, headers, body) = response.into_parts();
+        self.status = Some((status, reason));
+        for (key, value) in headers.iter() {
+            self.response_headers.append(key, value);
+        }
+
+        if !self.has_response_header("Date") {
+            self.write_date_header()?;
+        }
+
+        let has_body = !(status / 100 == 1 || status == 204 || status == 304);
+        let mut chunked = false;
+        if has_body && !self.has_response_header("Content-Length") {
+            match body.len() {
+                Some(len) => self.write_header("Content-Length", &len.to_string())?,
+                None if self.version == "HTTP/1.1" => {
+                    self.write_header("Transfer-Encoding", "chunked")?;
+                    chunked = true;
+                }
+                None => self.keep_alive = false,
+            }
+        }
+
+        if self.response_headers.has_token("Connection", "close") {
+            self.keep_alive = false;
+        } else if !self.keep_alive {
+            self.write_header("Connection", "close")?;
+        } else if self.version != "HTTP/1.1" && !self.has_response_header("Connection") {
+            self.write_header("Connection", "keep-alive")?;
+        }
+        self.finish_header()?;
+
+        if has_body && self.method != METHOD_HEAD {
+            match body {
+                Body::Empty => {}
+                Body::Bytes(bytes) => self.writer.write_all(&bytes)?,
+                Body::File(mut file) => {
+                    io::copy(&mut file, &mut self.writer)?;
+                }
+                Body::Stream(mut reader) if chunked => {
+                    let mut writer = ChunkedWriter::new(&mut self.writer);
+                    io::copy(&mut reader, &mut writer)?;
+                    writer.finish()?;
+                }
+                Body::Stream(mut reader) => {
+                    io::copy(&mut reader, &mut self.writer)?;
+                }
+                Body::Writer(write_body) if chunked => {
+                    // Buffer small writes so that each chunk isn't just a few bytes
+                    let mut writer = BufWriter::new(ChunkedWriter::new(&mut self.writer));
+                    write_body(&mut writer)?;
+                    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+                }
+                Body::Writer(write_body) => write_body(&mut self.writer)?,
+            }
+        }
+        self.writer.flush()
+    }
+
+    fn has_response_header(&self, key: &str) -> bool {
+        self.response_headers.contains(key)
     }
 }
 
+// Reading from a Connection yields the body of the current request only.
 impl Read for Connection {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.reader.read(buf)
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
     }
 }
 
@@ -105,40 +772,793 @@ impl Write for Connection {
 
 impl BufRead for Connection {
     fn fill_buf(&mut self) -> Result<&[u8]> {
-        self.reader.fill_buf()
+        match &mut self.body {
+            BodyReader::Length(0) => Ok(&[]),
+            BodyReader::Length(remaining) => {
+                let remaining = *remaining;
+                let buf = fill_body_buf(&mut self.reader)?;
+                Ok(&buf[..buf.len().min(remaining as usize)])
+            }
+            BodyReader::Chunked(decoder) => decoder.fill_buf(&mut self.reader),
+            BodyReader::Upgraded => self.reader.fill_buf(),
+        }
     }
 
     fn consume(&mut self, amt: usize) {
-        self.reader.consume(amt)
+        match &mut self.body {
+            BodyReader::Length(remaining) => {
+                *remaining -= amt as u64;
+                self.reader.consume(amt)
+            }
+            BodyReader::Chunked(decoder) => decoder.consume(&mut self.reader, amt),
+            BodyReader::Upgraded => self.reader.consume(amt),
+        }
     }
 }
 
-fn parse_request_line(line: &str) -> Result<(String, String, String)> {
-    let tokens: Vec<&str> = line.trim_end().split(' ').collect();
-    if tokens.len() == 3 {
-        Ok((
-            tokens[0].to_string(),
-            tokens[1].to_string(),
-            tokens[2].to_string(),
-        ))
-    } else {
-        Err(Error::new(
-            ErrorKind::InvalidInput,
-            format!("failed to parse {} as HTTP Request-Line", line),
-        ))
+enum BodyReader {
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    // The connection was switched to another protocol, see Connection::upgrade
+    Upgraded,
+}
+
+enum ChunkState {
+    Size,
+    Data,
+    Done,
+}
+
+struct ChunkedDecoder {
+    state: ChunkState,
+    remaining: u64,
+    total: u64,
+    limits: Limits,
+    trailers: Headers,
+}
+
+impl ChunkedDecoder {
+    fn new(limits: Limits) -> Self {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            remaining: 0,
+            total: 0,
+            limits,
+            trailers: Headers::new(),
+        }
+    }
+
+    fn trailers(&self) -> Option<&Headers> {
+        match self.state {
+            ChunkState::Done => Some(&self.trailers),
+            _ => None,
+        }
+    }
+
+    fn fill_buf<'a, R: BufRead>(&mut self, reader: &'a mut R) -> Result<&'a [u8]> {
+        loop {
+            match self.state {
+                ChunkState::Done => return Ok(&[]),
+                ChunkState::Data if self.remaining > 0 => break,
+                ChunkState::Data => {
+                    let line = read_line(reader, 2, 400)?.ok_or_else(unexpected_eof_in_body)?;
+                    if !line.is_empty() {
+                        return Err(http_error(400, "missing CRLF after chunk data".to_string()));
+                    }
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Size => {
+                    let line = read_line(reader, MAX_CHUNK_SIZE_LINE, 400)?
+                        .ok_or_else(unexpected_eof_in_body)?;
+                    self.remaining = parse_chunk_size(&line)?;
+                    self.total = self.total.saturating_add(self.remaining);
+                    if let Some(max) = self.limits.max_body_size {
+                        if self.total > max {
+                            return Err(body_too_large(max));
+                        }
+                    }
+                    if self.remaining == 0 {
+                        self.trailers = read_headers(reader, &self.limits)?;
+                        self.state = ChunkState::Done;
+                    } else {
+                        self.state = ChunkState::Data;
+                    }
+                }
+            }
+        }
+        let remaining = self.remaining;
+        let buf = fill_body_buf(reader)?;
+        Ok(&buf[..buf.len().min(remaining as usize)])
+    }
+
+    fn consume<R: BufRead>(&mut self, reader: &mut R, amt: usize) {
+        self.remaining -= amt as u64;
+        reader.consume(amt)
+    }
+}
+
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    decoder: ChunkedDecoder,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_limits(
+            inner,
+            Limits {
+                max_body_size: None,
+                ..Limits::default()
+            },
+        )
+    }
+
+    // The header limits apply to the trailer section.
+    pub fn with_limits(inner: R, limits: Limits) -> Self {
+        ChunkedReader {
+            inner,
+            decoder: ChunkedDecoder::new(limits),
+        }
+    }
+
+    pub fn trailers(&self) -> Option<&Headers> {
+        self.decoder.trailers()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for ChunkedReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.decoder.fill_buf(&mut self.inner)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.decoder.consume(&mut self.inner, amt)
     }
 }
 
-fn parse_header(line: &str) -> Result<(String, String)> {
-    let key_value: Vec<&str> = line.splitn(2, ':').collect();
-    if key_value.len() == 2 {
-        let key = key_value[0].trim().to_string();
-        let value = key_value[1].trim().to_string();
-        Ok((key, value))
+// Every write() becomes one chunk. The body must be terminated by finish() or
+// finish_with_trailers(); dropping the writer leaves the response incomplete.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner }
+    }
+
+    pub fn finish(self) -> Result<W> {
+        self.finish_with_trailers(&[])
+    }
+
+    pub fn finish_with_trailers(mut self, trailers: &[(&str, &str)]) -> Result<W> {
+        self.inner.write_all(b"0\r\n")?;
+        for (key, value) in trailers {
+            self.inner
+                .write_fmt(format_args!("{}: {}\r\n", key, value))?;
+        }
+        self.inner.write_all(b"\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner.write_fmt(format_args!("{:x}\r\n", buf.len()))?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn fill_body_buf<R: BufRead>(reader: &mut R) -> Result<&[u8]> {
+    let buf = reader.fill_buf()?;
+    if buf.is_empty() {
+        Err(unexpected_eof_in_body())
     } else {
-        Err(Error::new(
-            ErrorKind::InvalidInput,
-            format!("Invalid header {}", &line),
-        ))
+        Ok(buf)
+    }
+}
+
+fn unexpected_eof_in_body() -> Error {
+    Error::new(
+        ErrorKind::UnexpectedEof,
+        "connection closed before end of request body",
+    )
+}
+
+fn body_too_large(max: u64) -> Error {
+    http_error(413, format!("request body larger than {} bytes", max))
+}
+
+fn parse_chunk_size(line: &[u8]) -> Result<u64> {
+    let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+    let size = trim_ows(size);
+    if size.is_empty() || size.len() > 16 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(http_error(
+            400,
+            format!("Invalid chunk size {}", String::from_utf8_lossy(line)),
+        ));
+    }
+    // Only hex digits, at most 16 of them, so this can't fail
+    Ok(u64::from_str_radix(std::str::from_utf8(size).unwrap(), 16).unwrap())
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+// 16 hex digits, unique within the process and hard to guess across processes
+fn generate_request_id() -> String {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let seed = *SEED.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        nanos ^ (std::process::id() as u64).rotate_left(32)
+    });
+    // splitmix64, a bijection, so distinct counter values give distinct IDs
+    let mut z = seed.wrapping_add(
+        COUNTER
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15),
+    );
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    format!("{:016x}", z ^ (z >> 31))
+}
+
+const MAX_CHUNK_SIZE_LINE: usize = 4096;
+const MAX_EMPTY_LINES: usize = 4;
+
+struct RequestHead {
+    method: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    headers: Headers,
+    body: BodyReader,
+}
+
+// Returns None on EOF before the Request-Line.
+fn read_request_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Option<RequestHead>> {
+    // A few empty lines preceding the Request-Line are ignored (RFC 9112 2.2)
+    let mut empty_lines = 0;
+    let line = loop {
+        match read_line(reader, limits.max_request_line, 414)? {
+            Some(line) if line.is_empty() => {
+                empty_lines += 1;
+                if empty_lines > MAX_EMPTY_LINES {
+                    return Err(http_error(
+                        400,
+                        "too many empty lines before the Request-Line".to_string(),
+                    ));
+                }
+            }
+            Some(line) => break line,
+            None => return Ok(None),
+        }
+    };
+    let (method, target, version) = parse_request_line(&line)?;
+    let (path, query) = split_target(&target);
+    let headers = read_headers(reader, limits)?;
+
+    if version == "HTTP/1.1" && headers.get_all("Host").count() != 1 {
+        return Err(http_error(
+            400,
+            "HTTP/1.1 request needs exactly one Host header".to_string(),
+        ));
+    }
+    let body = body_reader(&headers, limits)?;
+
+    Ok(Some(RequestHead {
+        method,
+        path,
+        query,
+        version,
+        headers,
+        body,
+    }))
+}
+
+fn parse_request_line(line: &[u8]) -> Result<(String, String, String)> {
+    let invalid = || {
+        http_error(
+            400,
+            format!(
+                "failed to parse {} as HTTP Request-Line",
+                String::from_utf8_lossy(line)
+            ),
+        )
+    };
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let tokens: Vec<&str> = line.split(' ').collect();
+    if tokens.len() != 3 {
+        return Err(invalid());
+    }
+    let (method, target, version) = (tokens[0], tokens[1], tokens[2]);
+    if !is_token(method) || target.is_empty() || !target.bytes().all(|b| b > 0x20 && b < 0x7f) {
+        return Err(invalid());
+    }
+
+    let digits = version.strip_prefix("HTTP/").map(|v| v.as_bytes());
+    match digits {
+        Some([b'1', b'.', b'0']) | Some([b'1', b'.', b'1']) => {}
+        Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit() => {
+            return Err(http_error(505, format!("{} is not supported", version)))
+        }
+        _ => return Err(invalid()),
+    }
+
+    Ok((method.to_string(), target.to_string(), version.to_string()))
+}
+
+// The status, reason and headers of a response, for clients such as proxy::Proxy.
+pub(crate) fn read_response_head<R: BufRead>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<(u16, String, Headers)> {
+    let line = read_line(reader, limits.max_request_line, 502)?.ok_or_else(|| {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed before the response",
+        )
+    })?;
+    let line = String::from_utf8_lossy(&line);
+    let mut parts = line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts
+        .next()
+        .filter(|status| status.len() == 3)
+        .and_then(|status| status.parse().ok());
+    let status = match status {
+        Some(status) if version.starts_with("HTTP/1.") => status,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid status line {}", line),
+            ))
+        }
+    };
+    let reason = parts.next().unwrap_or("").to_string();
+    let headers = read_headers(reader, limits)?;
+    Ok((status, reason, headers))
+}
+
+// Reads the header section up to and including the empty line that terminates it.
+fn read_headers<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Headers> {
+    let mut headers = Headers::new();
+    let mut size = 0;
+    loop {
+        let limit = limits.max_header_size.saturating_sub(size) + 2;
+        let line = read_line(reader, limit, 431)?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed in HTTP headers",
+            )
+        })?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        size += line.len() + 2;
+        if headers.len() >= limits.max_headers {
+            return Err(http_error(
+                431,
+                format!("more than {} header fields", limits.max_headers),
+            ));
+        }
+        let (key, value) = parse_header(&line)?;
+        headers.append(&key, &value);
+    }
+}
+
+fn parse_header(line: &[u8]) -> Result<(String, String)> {
+    let invalid = || {
+        http_error(
+            400,
+            format!("Invalid header {}", String::from_utf8_lossy(line)),
+        )
+    };
+    // Obsolete line folding is rejected rather than unfolded (RFC 9112 5.2)
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return Err(invalid());
+    }
+    let colon = line.iter().position(|&b| b == b':').ok_or_else(invalid)?;
+    let key = std::str::from_utf8(&line[..colon]).map_err(|_| invalid())?;
+    if !is_token(key) {
+        return Err(invalid());
+    }
+    let value = trim_ows(&line[colon + 1..]);
+    if value.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+        return Err(invalid());
+    }
+    Ok((key.to_string(), String::from_utf8_lossy(value).into_owned()))
+}
+
+fn body_reader(headers: &Headers, limits: &Limits) -> Result<BodyReader> {
+    if headers.contains("Transfer-Encoding") {
+        if headers.contains("Content-Length") {
+            return Err(http_error(
+                400,
+                "both Transfer-Encoding and Content-Length".to_string(),
+            ));
+        }
+        let codings: Vec<&str> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim())
+            .filter(|coding| !coding.is_empty())
+            .collect();
+        return match codings.as_slice() {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => {
+                Ok(BodyReader::Chunked(ChunkedDecoder::new(*limits)))
+            }
+            _ => Err(http_error(
+                501,
+                format!("Unsupported Transfer-Encoding {}", codings.join(", ")),
+            )),
+        };
+    }
+
+    // Repeated Content-Length fields are only acceptable if they all agree (RFC 9112 6.3)
+    let mut len = None;
+    for value in headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        if value.is_empty() || value.len() > 19 || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(http_error(400, format!("Invalid Content-Length {}", value)));
+        }
+        let value: u64 = value.parse().unwrap();
+        if len.is_some_and(|len| len != value) {
+            return Err(http_error(400, "conflicting Content-Length".to_string()));
+        }
+        len = Some(value);
+    }
+    let len = len.unwrap_or(0);
+    match limits.max_body_size {
+        Some(max) if len > max => Err(body_too_large(max)),
+        _ => Ok(BodyReader::Length(len)),
+    }
+}
+
+// Returns the number of bytes the first request in buf occupies, including its body, or None
+// if buf doesn't hold a complete request yet.
+pub fn request_length(buf: &[u8], limits: &Limits) -> Result<Option<usize>> {
+    RequestScanner::new(*limits).scan(buf)
+}
+
+// Finds the end of the first request in a buffer that grows as data arrives, like
+// request_length() but resuming where the previous call stopped instead of parsing the buffer
+// from the start again. The buffer may only be appended to between calls; a new scanner is
+// needed once the request is removed from it.
+pub struct RequestScanner {
+    limits: Limits,
+    state: ScanState,
+    // Everything before pos has been scanned
+    pos: usize,
+    body_size: u64,
+}
+
+enum ScanState {
+    // Skipping the empty lines that may precede the Request-Line
+    Start(usize),
+    // The head starting at start, whose current line starts at line
+    Head { start: usize, line: usize },
+    // The request ends at the given offset
+    End(usize),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkEnd,
+    // The trailer section starting at start, whose current line starts at line
+    Trailers { start: usize, line: usize },
+}
+
+impl RequestScanner {
+    pub fn new(limits: Limits) -> Self {
+        RequestScanner {
+            limits,
+            state: ScanState::Start(0),
+            pos: 0,
+            body_size: 0,
+        }
+    }
+
+    // Whether the head of the request has been received, so that only its body is missing.
+    pub fn is_head_complete(&self) -> bool {
+        !matches!(self.state, ScanState::Start(_) | ScanState::Head { .. })
+    }
+
+    pub fn scan(&mut self, buf: &[u8]) -> Result<Option<usize>> {
+        loop {
+            let rest = &buf[self.pos..];
+            match self.state {
+                ScanState::Start(empty_lines) => {
+                    let skip = if rest.starts_with(b"\r\n") {
+                        2
+                    } else if rest.starts_with(b"\n") {
+                        1
+                    } else if rest.is_empty() || rest == b"\r" {
+                        return Ok(None);
+                    } else {
+                        self.state = ScanState::Head {
+                            start: self.pos,
+                            line: self.pos,
+                        };
+                        continue;
+                    };
+                    if empty_lines >= MAX_EMPTY_LINES {
+                        return Err(http_error(
+                            400,
+                            "too many empty lines before the Request-Line".to_string(),
+                        ));
+                    }
+                    self.pos += skip;
+                    self.state = ScanState::Start(empty_lines + 1);
+                }
+                ScanState::Head { start, line } => {
+                    let i = match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) => self.pos + i,
+                        None => {
+                            self.pos = buf.len();
+                            return self.check_head_size(&buf[start..], line > start);
+                        }
+                    };
+                    self.pos = i + 1;
+                    if !matches!(&buf[line..i], b"" | b"\r") {
+                        self.state = ScanState::Head {
+                            start,
+                            line: self.pos,
+                        };
+                        continue;
+                    }
+                    let head = read_request_head(&mut &buf[start..self.pos], &self.limits)?
+                        .ok_or_else(|| http_error(400, "no HTTP Request-Line".to_string()))?;
+                    self.state = match head.body {
+                        BodyReader::Length(len) => ScanState::End(self.pos + len as usize),
+                        BodyReader::Chunked(_) => ScanState::ChunkSize,
+                        BodyReader::Upgraded => unreachable!("request heads don't upgrade"),
+                    };
+                }
+                ScanState::End(end) if buf.len() >= end => return Ok(Some(end)),
+                ScanState::End(_) => return Ok(None),
+                ScanState::ChunkSize => {
+                    let i = match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) if i < MAX_CHUNK_SIZE_LINE => i,
+                        None if rest.len() < MAX_CHUNK_SIZE_LINE => return Ok(None),
+                        _ => {
+                            return Err(http_error(
+                                400,
+                                format!("line longer than {} bytes", MAX_CHUNK_SIZE_LINE),
+                            ))
+                        }
+                    };
+                    let line = &rest[..i];
+                    let size = parse_chunk_size(line.strip_suffix(b"\r").unwrap_or(line))?;
+                    self.body_size = self.body_size.saturating_add(size);
+                    if let Some(max) = self.limits.max_body_size {
+                        if self.body_size > max {
+                            return Err(body_too_large(max));
+                        }
+                    }
+                    self.pos += i + 1;
+                    self.state = if size == 0 {
+                        ScanState::Trailers {
+                            start: self.pos,
+                            line: self.pos,
+                        }
+                    } else {
+                        ScanState::ChunkData(size)
+                    };
+                }
+                ScanState::ChunkData(remaining) => {
+                    if (rest.len() as u64) < remaining {
+                        self.pos = buf.len();
+                        self.state = ScanState::ChunkData(remaining - rest.len() as u64);
+                        return Ok(None);
+                    }
+                    self.pos += remaining as usize;
+                    self.state = ScanState::ChunkEnd;
+                }
+                ScanState::ChunkEnd => {
+                    self.pos += match rest {
+                        [b'\n', ..] => 1,
+                        [b'\r', b'\n', ..] => 2,
+                        [] | [b'\r'] => return Ok(None),
+                        _ => {
+                            return Err(http_error(
+                                400,
+                                "missing CRLF after chunk data".to_string(),
+                            ))
+                        }
+                    };
+                    self.state = ScanState::ChunkSize;
+                }
+                ScanState::Trailers { start, line } => {
+                    let i = match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) => self.pos + i,
+                        None => {
+                            self.pos = buf.len();
+                            return self.check_trailers_size(&buf[start..]);
+                        }
+                    };
+                    self.pos = i + 1;
+                    if matches!(&buf[line..i], b"" | b"\r") {
+                        read_headers(&mut &buf[start..self.pos], &self.limits)?;
+                        self.state = ScanState::End(self.pos);
+                    } else {
+                        self.state = ScanState::Trailers {
+                            start,
+                            line: self.pos,
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    // An incomplete head that can't fit the limits is rejected without waiting for the rest,
+    // with the status parsing it would give.
+    fn check_head_size(&self, head: &[u8], has_request_line: bool) -> Result<Option<usize>> {
+        let max = if has_request_line {
+            self.limits.max_request_line + self.limits.max_header_size + 2
+        } else {
+            self.limits.max_request_line
+        };
+        if head.len() <= max {
+            return Ok(None);
+        }
+        read_request_head(&mut &head[..], &self.limits)?;
+        Err(http_error(431, "request head too large".to_string()))
+    }
+
+    fn check_trailers_size(&self, trailers: &[u8]) -> Result<Option<usize>> {
+        if trailers.len() <= self.limits.max_header_size + 2 {
+            return Ok(None);
+        }
+        read_headers(&mut &trailers[..], &self.limits)?;
+        Err(http_error(431, "trailer section too large".to_string()))
+    }
+}
+
+// Reads a line terminated by LF or CRLF, without the terminator. Fails with status once the
+// line is longer than limit, so that a client can't make the server buffer without bound.
+// Returns None on EOF before the line.
+fn read_line<R: BufRead>(reader: &mut R, limit: usize, status: u16) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return if line.is_empty() {
+                Ok(None)
+            } else {
+                Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed in the middle of a line",
+                ))
+            };
+        }
+        let (used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (available.len(), false),
+        };
+        if line.len() + used > limit {
+            return Err(http_error(
+                status,
+                format!("line longer than {} bytes", limit),
+            ));
+        }
+        line.extend_from_slice(&available[..used]);
+        reader.consume(used);
+        if done {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Ok(Some(line));
+        }
+    }
+}
+
+fn is_timeout(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn trim_ows(mut s: &[u8]) -> &[u8] {
+    while let [b' ', rest @ ..] | [b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' '] | [rest @ .., b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
+// Origin-form targets are split into path and query. Absolute-form targets, which proxies send,
+// are reduced to the same.
+fn split_target(target: &str) -> (String, Option<String>) {
+    let target = match target.find("://") {
+        Some(i) if !target[..i].contains('/') => {
+            let rest = &target[i + 3..];
+            match rest.find(['/', '?']) {
+                Some(j) if rest[j..].starts_with('/') => &rest[j..],
+                Some(j) => return ("/".to_string(), Some(rest[j + 1..].to_string())),
+                None => "/",
+            }
+        }
+        _ => target,
+    };
+    match target.find('?') {
+        Some(i) => (target[..i].to_string(), Some(target[i + 1..].to_string())),
+        None => (target.to_string(), None),
     }
 }
@@ -1,87 +1,308 @@
+use std::any::Any;
 use std::collections::BTreeMap;
-use std::io::Result;
-use std::net::{TcpListener, TcpStream};
+use std::io::{Error, Result};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::http::Connection;
+#[cfg(feature = "async")]
+use crate::async_server::AsyncHandler;
+use crate::extract::AppState;
+use crate::http::{error_status, Connection, Limits, METHOD_GET, METHOD_HEAD, METHOD_OPTIONS};
+use crate::logging::{LogSink, Stderr};
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+use crate::route::{Params, Tree};
 
+#[cfg(feature = "async")]
+pub mod async_server;
+pub mod auth;
+pub mod cache;
+pub mod compression;
+pub mod cookie;
+pub mod cors;
+pub mod date;
+pub mod extract;
 pub mod http;
+pub mod logging;
+pub mod middleware;
+pub mod pool;
+pub mod proxy;
+pub mod rate_limit;
+pub mod response;
+pub mod route;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod static_files;
+pub mod template;
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod websocket;
 
 pub trait Handler: Send + Sync + 'static {
-    fn handle(&self, conn: &mut Connection) -> Result<()>;
+    fn handle(&self, conn: &mut Connection) -> Result<Response>;
 }
 
 impl<F> Handler for F
 where
-    F: Send + Sync + Fn(&mut Connection) -> Result<()> + 'static,
+    F: Send + Sync + Fn(&mut Connection) -> Result<Response> + 'static,
 {
-    fn handle(&self, conn: &mut Connection) -> Result<()> {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
         (*self)(conn)
     }
 }
 
+// Renders the response to a request whose middleware or handler failed. The status is the one
+// of an http::HttpError, or 500 for any other error and for panics.
+pub trait ErrorHandler: Send + Sync + 'static {
+    fn handle(&self, conn: &mut Connection, status: u16, error: &Error) -> Response;
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Send + Sync + Fn(&mut Connection, u16, &Error) -> Response + 'static,
+{
+    fn handle(&self, conn: &mut Connection, status: u16, error: &Error) -> Response {
+        (*self)(conn, status, error)
+    }
+}
+
+pub struct Route {
+    handler: Arc<dyn Handler>,
+    #[cfg(feature = "async")]
+    async_handler: Option<Arc<dyn AsyncHandler>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Route {
+    fn new(handler: Arc<dyn Handler>) -> Self {
+        Route {
+            handler,
+            #[cfg(feature = "async")]
+            async_handler: None,
+            middlewares: Vec::new(),
+        }
+    }
+
+    pub fn add_middleware<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+}
+
 pub struct Router {
-    routes: BTreeMap<String, BTreeMap<String, Arc<dyn Handler>>>,
+    routes: Tree<BTreeMap<String, Route>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
     not_found_handler: Arc<dyn Handler>,
     method_not_allowed_handler: Arc<dyn Handler>,
+    error_handler: Arc<dyn ErrorHandler>,
+    keep_alive_timeout: Option<Duration>,
+    header_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_requests_per_connection: usize,
+    max_connections: usize,
+    worker_threads: usize,
+    queue_size: usize,
+    shutdown_timeout: Duration,
+    limits: Limits,
+    state: Arc<AppState>,
+    error_log: Arc<dyn LogSink>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Router {
     pub fn new() -> Self {
         Router {
-            routes: BTreeMap::new(),
+            routes: Tree::new(),
+            middlewares: Vec::new(),
             not_found_handler: Arc::new(default_not_found_handler),
             method_not_allowed_handler: Arc::new(default_method_not_allowed_handler),
+            error_handler: Arc::new(default_error_handler),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            header_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_requests_per_connection: 100,
+            max_connections: 1024,
+            worker_threads: 64,
+            queue_size: 256,
+            shutdown_timeout: Duration::from_secs(30),
+            limits: Limits::default(),
+            state: Arc::default(),
+            error_log: Arc::new(Stderr),
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
-    pub fn add_handler<H: Handler>(&mut self, method: &str, path: &str, handler: H) {
-        if !self.routes.contains_key(path) {
-            self.routes.insert(path.to_string(), BTreeMap::new());
-        }
-        self.routes
-            .get_mut(path)
-            .unwrap()
-            .insert(method.to_string(), Arc::new(handler));
-    }
-
-    pub fn listen(&mut self, addr: &str) -> Result<()> {
-        let listener = TcpListener::bind(addr)?;
-        for stream in listener.incoming() {
-            if let Err(e) = stream {
-                eprintln!("{}", e);
-                continue;
-            }
-            if let Err(e) = self.handle_client(stream?) {
-                eprintln!("{}", e);
-                continue;
-            };
-        }
-        Ok(())
+    pub fn add_handler<H: Handler>(&mut self, method: &str, path: &str, handler: H) -> &mut Route {
+        let routes = self.routes.get_or_insert_with(path, BTreeMap::new);
+        routes.insert(method.to_string(), Route::new(Arc::new(handler)));
+        routes.get_mut(method).unwrap()
     }
 
-    fn handle_client(&self, stream: TcpStream) -> Result<()> {
-        let mut conn = Connection::new(stream)?;
+    pub fn add_middleware<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
 
-        let handler = self.get_handler(&conn.method, &conn.path);
+    pub fn set_not_found_handler<H: Handler>(&mut self, handler: H) -> &mut Self {
+        self.not_found_handler = Arc::new(handler);
+        self
+    }
 
-        std::thread::spawn(move || {
-            if let Err(e) = handler.handle(&mut conn) {
-                eprintln!("{}", e);
-            }
+    // Answers requests for a known path with another method. The response gets an Allow header
+    // listing the route's methods unless the handler set one.
+    pub fn set_method_not_allowed_handler<H: Handler>(&mut self, handler: H) -> &mut Self {
+        self.method_not_allowed_handler = Arc::new(handler);
+        self
+    }
+
+    pub fn set_error_handler<H: ErrorHandler>(&mut self, handler: H) -> &mut Self {
+        self.error_handler = Arc::new(handler);
+        self
+    }
+
+    pub fn set_keep_alive_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    // Time allowed from the first byte of a request to the end of its head. Requests sent more
+    // slowly get 408, so that slow clients can't tie connections up.
+    pub fn set_header_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    // How long a read of a request body may wait for data.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    // How long a write of a response may block on a client that doesn't read.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    // Connections open at once, including queued ones. Beyond this, new connections get 503.
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Self {
+        self.max_connections = max;
+        self
+    }
+
+    pub fn set_max_requests_per_connection(&mut self, max: usize) -> &mut Self {
+        self.max_requests_per_connection = max;
+        self
+    }
+
+    // Each worker serves one connection at a time, including its keep-alive requests.
+    pub fn set_worker_threads(&mut self, threads: usize) -> &mut Self {
+        self.worker_threads = threads;
+        self
+    }
+
+    // Connections waiting for a free worker. Beyond this, new connections get 503.
+    pub fn set_queue_size(&mut self, size: usize) -> &mut Self {
+        self.queue_size = size;
+        self
+    }
+
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    // Size limits on requests, see http::Limits.
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    // Where errors that no handler could answer go, such as failed accepts or broken
+    // connections. Standard error by default.
+    pub fn set_error_log<S: LogSink>(&mut self, sink: S) -> &mut Self {
+        self.error_log = Arc::new(sink);
+        self
+    }
+
+    // Serves HTTPS instead of plain HTTP from bind() and listen().
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, config: tls::TlsConfig) -> &mut Self {
+        self.tls = Some(config.server_config());
+        self
+    }
+
+    pub fn listen(self, addr: &str) -> Result<()> {
+        let server = self.bind(addr)?;
+        #[cfg(unix)]
+        server.stop_on_signals()?;
+        server.wait()
+    }
+
+    fn dispatch(&self, conn: &mut Connection) -> Result<Response> {
+        let (handler, middlewares, params) = self.get_handler(&conn.method, &conn.path);
+        conn.params = params;
+        if !self.state.is_empty() {
+            conn.extensions_mut().insert(self.state.clone());
+        }
+        let next = Next::new(&middlewares, handler.as_ref());
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(conn))) {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => self.render_error(conn, e),
+            Err(panic) => self.render_error(conn, panic_error(panic.as_ref())),
+        }
+    }
+
+    // Turns a failed request into a response from the error handler, unless part of a response
+    // was already sent. Errors without a status are logged, and the connection is closed since
+    // the request body may not have been read.
+    fn render_error(&self, conn: &mut Connection, e: Error) -> Result<Response> {
+        if conn.is_header_finished() {
+            return Err(e);
+        }
+        let status = error_status(&e).unwrap_or_else(|| {
+            self.error_log
+                .log(&format!("{} {}: {}", conn.method, conn.path, e));
+            500
         });
-        Ok(())
+        conn.set_keep_alive(false);
+        Ok(self.error_handler.handle(conn, status, &e))
     }
 
-    fn get_handler(&self, method: &str, path: &str) -> Arc<dyn Handler> {
-        if let Some(handlers) = self.routes.get(path) {
-            if let Some(handler) = handlers.get(method) {
-                handler.clone()
+    fn get_handler(
+        &self,
+        method: &str,
+        path: &str,
+    ) -> (Arc<dyn Handler>, Vec<Arc<dyn Middleware>>, Params) {
+        let mut middlewares = self.middlewares.clone();
+        if let Some((routes, params)) = self.routes.find(path) {
+            // HEAD is answered by the GET handler, whose body Connection::send leaves out.
+            let route = match routes.get(method) {
+                None if method == METHOD_HEAD => routes.get(METHOD_GET),
+                route => route,
+            };
+            if let Some(route) = route {
+                middlewares.extend(route.middlewares.iter().cloned());
+                (route.handler.clone(), middlewares, params)
+            } else if method == METHOD_OPTIONS {
+                let handler = Arc::new(AllowedMethods(allowed_methods(routes)));
+                (handler, middlewares, params)
             } else {
-                self.method_not_allowed_handler.clone()
+                let handler = Arc::new(MethodNotAllowed {
+                    allow: allowed_methods(routes),
+                    handler: self.method_not_allowed_handler.clone(),
+                });
+                (handler, middlewares, params)
             }
         } else {
-            self.not_found_handler.clone()
+            (self.not_found_handler.clone(), middlewares, Params::new())
         }
     }
 }
@@ -92,10 +313,62 @@ impl Default for Router {
     }
 }
 
-fn default_not_found_handler(conn: &mut Connection) -> Result<()> {
-    conn.write_status(404, "Not Found")
+fn default_not_found_handler(_conn: &mut Connection) -> Result<Response> {
+    Ok(Response::new(404))
+}
+
+fn default_method_not_allowed_handler(_conn: &mut Connection) -> Result<Response> {
+    Ok(Response::new(405))
+}
+
+fn default_error_handler(_conn: &mut Connection, status: u16, _error: &Error) -> Response {
+    Response::new(status)
+}
+
+struct MethodNotAllowed {
+    allow: String,
+    handler: Arc<dyn Handler>,
+}
+
+impl Handler for MethodNotAllowed {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        let mut response = self.handler.handle(conn)?;
+        if !response.headers().contains("Allow") {
+            response.set_header("Allow", &self.allow);
+        }
+        Ok(response)
+    }
+}
+
+// Answers OPTIONS for routes without an OPTIONS handler of their own.
+struct AllowedMethods(String);
+
+impl Handler for AllowedMethods {
+    fn handle(&self, _conn: &mut Connection) -> Result<Response> {
+        Ok(Response::new(204).with_header("Allow", &self.0))
+    }
+}
+
+// The Allow header value for a route, including the methods answered automatically.
+fn allowed_methods(routes: &BTreeMap<String, Route>) -> String {
+    let mut methods: Vec<&str> = routes.keys().map(String::as_str).collect();
+    if routes.contains_key(METHOD_GET) && !routes.contains_key(METHOD_HEAD) {
+        methods.push(METHOD_HEAD);
+    }
+    if !routes.contains_key(METHOD_OPTIONS) {
+        methods.push(METHOD_OPTIONS);
+    }
+    methods.sort_unstable();
+    methods.join(", ")
 }
 
-fn default_method_not_allowed_handler(conn: &mut Connection) -> Result<()> {
-    conn.write_status(405, "Method Not Allowed")
+pub(crate) fn panic_error(panic: &(dyn Any + Send)) -> Error {
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    };
+    Error::other(format!("handler panicked: {}", message))
 }
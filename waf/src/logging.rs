@@ -0,0 +1,238 @@
+use std::io::{Result, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+
+use crate::http::{error_status, Connection};
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+
+// Where log lines go. Lines come without a trailing newline.
+pub trait LogSink: Send + Sync + 'static {
+    fn log(&self, line: &str);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    fn log(&self, line: &str) {
+        (*self)(line)
+    }
+}
+
+pub struct Stderr;
+
+impl LogSink for Stderr {
+    fn log(&self, line: &str) {
+        eprintln!("{}", line);
+    }
+}
+
+pub struct Stdout;
+
+impl LogSink for Stdout {
+    fn log(&self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+// Appends lines to a writer such as a File. Write errors are ignored.
+pub struct WriterSink<W: Write + Send + 'static>(Mutex<W>);
+
+impl<W: Write + Send + 'static> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        WriterSink(Mutex::new(writer))
+    }
+}
+
+impl<W: Write + Send + 'static> LogSink for WriterSink<W> {
+    fn log(&self, line: &str) {
+        let mut writer = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(writer, "{}", line);
+        let _ = writer.flush();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // host ident authuser [date] "request" status bytes
+    Common,
+    // Common followed by "referer" "user-agent"
+    Combined,
+    // One JSON object per line, including the request ID and the latency
+    Json,
+}
+
+// Middleware writing one line per request to a sink:
+//
+//     router.add_middleware(RequestId::new());
+//     router.add_middleware(AccessLog::new(LogFormat::Combined, Stdout));
+//
+// The latency is measured until the handler returns its response, so it doesn't include
+// sending a streamed body.
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Arc<dyn LogSink>,
+}
+
+impl AccessLog {
+    pub fn new<S: LogSink>(format: LogFormat, sink: S) -> Self {
+        AccessLog {
+            format,
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let start = Instant::now();
+        let result = next.run(conn);
+        let entry = Entry {
+            conn,
+            status: match &result {
+                Ok(response) => response.status(),
+                Err(e) => error_status(e).unwrap_or(500),
+            },
+            size: match &result {
+                Ok(response) => response.body().len(),
+                Err(_) => None,
+            },
+            micros: start.elapsed().as_micros(),
+        };
+        let line = match self.format {
+            LogFormat::Common => entry.common(),
+            LogFormat::Combined => entry.combined(),
+            LogFormat::Json => entry.json(),
+        };
+        self.sink.log(&line);
+        result
+    }
+}
+
+// Returns the request's ID in X-Request-Id. Add it before AccessLog so that both agree. An
+// X-Request-Id sent by the client is only honored instead of the generated ID if trusted.
+pub struct RequestId {
+    trust_incoming: bool,
+}
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId {
+            trust_incoming: false,
+        }
+    }
+
+    // Whether to keep a client's X-Request-Id, e.g. behind a proxy that sets it. IDs longer than
+    // 128 bytes or with characters other than letters, digits and "-_.:+=/" are replaced.
+    pub fn with_trust_incoming(mut self, trust: bool) -> Self {
+        self.trust_incoming = trust;
+        self
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        if self.trust_incoming {
+            let incoming = conn.header("X-Request-Id").map(|id| id.trim().to_string());
+            if let Some(id) = incoming.filter(|id| is_valid_request_id(id)) {
+                conn.set_request_id(&id);
+            }
+        }
+        let id = conn.request_id().to_string();
+        let mut response = next.run(conn)?;
+        response.set_header("X-Request-Id", &id);
+        Ok(response)
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:+=/".contains(&b))
+}
+
+struct Entry<'a> {
+    conn: &'a Connection,
+    status: u16,
+    size: Option<u64>,
+    micros: u128,
+}
+
+impl<'a> Entry<'a> {
+    fn common(&self) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.remote(),
+            Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            escape_quoted(&self.request_line()),
+            self.status,
+            self.size.map_or("-".to_string(), |size| size.to_string()),
+        )
+    }
+
+    fn combined(&self) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.common(),
+            escape_quoted(self.conn.header("Referer").unwrap_or("-")),
+            escape_quoted(self.conn.header("User-Agent").unwrap_or("-")),
+        )
+    }
+
+    fn json(&self) -> String {
+        let mut fields = json!({
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "request_id": self.conn.request_id(),
+            "remote": self.remote(),
+            "method": self.conn.method,
+            "path": self.conn.path,
+            "query": self.conn.query,
+            "version": self.conn.version,
+            "status": self.status,
+            "size": self.size,
+            "duration_ms": self.micros as f64 / 1000.0,
+        });
+        for (key, header) in &[("referer", "Referer"), ("user_agent", "User-Agent")] {
+            if let Some(value) = self.conn.header(header) {
+                fields[*key] = value.into();
+            }
+        }
+        fields.to_string()
+    }
+
+    fn remote(&self) -> String {
+        self.conn
+            .peer_addr()
+            .map_or("-".to_string(), |addr| addr.ip().to_string())
+    }
+
+    fn request_line(&self) -> String {
+        match &self.conn.query {
+            Some(query) => format!(
+                "{} {}?{} {}",
+                self.conn.method, self.conn.path, query, self.conn.version
+            ),
+            None => format!(
+                "{} {} {}",
+                self.conn.method, self.conn.path, self.conn.version
+            ),
+        }
+    }
+}
+
+// Keeps a quoted field of the Common Log Format on one line and unambiguous.
+fn escape_quoted(s: &str) -> String {
+    s.escape_default().to_string()
+}
@@ -1,67 +1,89 @@
 use std::io;
-use std::io::Write;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use serde_json::json;
+
+use waf::compression::Compression;
+use waf::extract::{handler, Path, State};
 use waf::http::{Connection, METHOD_GET};
+use waf::logging::{AccessLog, LogFormat, RequestId, Stdout};
+use waf::middleware::Next;
+use waf::response::Response;
+use waf::static_files::StaticFiles;
+use waf::template::Templates;
+use waf::websocket::{Message, WebSocket, WebSocketHandler};
 use waf::Router;
 
-fn main() -> io::Result<()> {
-    let counter = AtomicUsize::new(1);
+struct Visitors(AtomicUsize);
 
+fn main() -> io::Result<()> {
     let mut router = Router::new();
+    router.add_state(Visitors(AtomicUsize::new(1)));
+    router.add_state(Templates::new("templates"));
 
-    router.add_handler(METHOD_GET, "/", move |conn: &mut Connection| {
-        let _headers = conn.read_headers()?;
-
-        // Create Body
-        let count = counter.fetch_add(1, Ordering::SeqCst);
-        let body = format!(
-            r###"
-            <!DOCTYPE html>
-            <html lang="ja">
-                <head>
-                    <title>ほーむぺーじ</title>
-                </head>
-                <body>
-                    <p>あなたは {} 人目の訪問者です。</p>
-                </body>
-            </html>
-        "###,
-            count
-        );
-
-        // Write Headers
-        conn.write_status(200, "OK")?;
-        conn.write_header("Content-Type", "text/html; charset=UTF-8")?;
-        conn.write_header("Content-Length", &format!("{}", body.len()))?;
-        conn.finish_header()?;
+    router.add_middleware(RequestId::new());
+    router.add_middleware(AccessLog::new(LogFormat::Combined, Stdout));
+    router.add_middleware(Compression::new());
+    router.add_middleware(|conn: &mut Connection, next: Next| {
+        let mut response = next.run(conn)?;
+        response.set_header("Server", "Example");
+        Ok(response)
+    });
 
-        // Write Body
-        conn.write_all(body.as_bytes())?;
+    router.add_handler(
+        METHOD_GET,
+        "/",
+        handler(
+            |State(visitors): State<Visitors>, State(templates): State<Templates>| {
+                let count = visitors.0.fetch_add(1, Ordering::SeqCst);
+                templates.response("index.html", &json!({ "count": count }))
+            },
+        ),
+    );
 
-        Ok(())
+    router.add_handler(METHOD_GET, "/json", |_conn: &mut Connection| {
+        Ok(Response::json("{\"message\":\"Hello, World!\"}\n").with_header("Connection", "close"))
     });
 
-    router.add_handler(METHOD_GET, "/json", |conn: &mut Connection| {
-        let _headers = conn.read_headers()?;
+    router.add_handler(
+        METHOD_GET,
+        "/users/:id<uint>",
+        handler(|Path(id): Path<u64>| Ok(Response::json(format!("{{\"id\":{}}}\n", id)))),
+    );
 
-        // Create Body
-        let body = "{\"message\":\"Hello, World!\"}\n";
+    router.add_handler(METHOD_GET, "/home", |_conn: &mut Connection| {
+        Ok(Response::redirect(301, "/"))
+    });
 
-        // Write Headers
-        conn.write_status(200, "OK")?;
-        conn.write_header("Content-Type", "application/json; charset=UTF-8")?;
-        conn.write_header("Content-Length", &format!("{}", body.len()))?;
-        conn.write_header("Connection", "close")?;
-        conn.write_header("Server", "Example")?;
-        conn.write_date_header()?;
-        conn.finish_header()?;
+    router.add_handler(METHOD_GET, "/count/:n<uint>", |conn: &mut Connection| {
+        let n: u64 = conn.params.parse("n")?;
+        Ok(Response::streaming(move |writer| {
+            for i in 1..=n {
+                writeln!(writer, "{}", i)?;
+            }
+            Ok(())
+        })
+        .with_header("Content-Type", "text/plain; charset=UTF-8"))
+    });
 
-        // Write Body
-        conn.write_all(body.as_bytes())?;
+    router.add_handler(
+        METHOD_GET,
+        "/echo",
+        WebSocketHandler::new(|ws: &mut WebSocket| {
+            while let Some(message) = ws.recv()? {
+                if let Message::Text(_) | Message::Binary(_) = message {
+                    ws.send(&message)?;
+                }
+            }
+            Ok(())
+        }),
+    );
 
-        Ok(())
-    });
+    router.add_handler(
+        METHOD_GET,
+        "/static/*path",
+        StaticFiles::new("static").with_listing(true),
+    );
 
     router.listen("localhost:8080")
 }
@@ -0,0 +1,42 @@
+use std::io::Result;
+use std::sync::Arc;
+
+use crate::http::Connection;
+use crate::response::Response;
+use crate::Handler;
+
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response>;
+}
+
+impl<F> Middleware for F
+where
+    F: Send + Sync + Fn(&mut Connection, Next<'_>) -> Result<Response> + 'static,
+{
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        (*self)(conn, next)
+    }
+}
+
+// The rest of the chain. A middleware short-circuits by returning its own response instead of
+// calling run(), and can modify the response returned by run() before passing it on.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middlewares: &'a [Arc<dyn Middleware>], handler: &'a dyn Handler) -> Self {
+        Next {
+            middlewares,
+            handler,
+        }
+    }
+
+    pub fn run(self, conn: &mut Connection) -> Result<Response> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(conn, Next::new(rest, self.handler)),
+            None => self.handler.handle(conn),
+        }
+    }
+}
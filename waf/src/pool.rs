@@ -0,0 +1,74 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+// A fixed number of worker threads fed through a bounded queue. When every worker is busy and
+// the queue is full, submit() hands the item back instead of blocking.
+pub struct WorkerPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub fn new<F>(size: usize, queue_size: usize, work: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "worker pool needs at least one thread");
+
+        let (sender, receiver) = sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let work = Arc::new(work);
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let work = work.clone();
+                std::thread::spawn(move || run_worker(&receiver, work.as_ref()))
+            })
+            .collect();
+
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub fn submit(&self, item: T) -> std::result::Result<(), T> {
+        match self.sender.as_ref().unwrap().try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) | Err(TrySendError::Disconnected(item)) => Err(item),
+        }
+    }
+
+    // Stops accepting work and waits until the queued items have been processed.
+    pub fn join(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run_worker<T, F: Fn(T)>(receiver: &Mutex<Receiver<T>>, work: &F) {
+    loop {
+        let item = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match item {
+            Ok(item) => work(item),
+            Err(_) => return,
+        }
+    }
+}
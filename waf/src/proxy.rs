@@ -0,0 +1,434 @@
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Take, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::http::{
+    read_response_head, ChunkedReader, ChunkedWriter, Connection, Headers, HttpError, Limits,
+    METHOD_DELETE, METHOD_GET, METHOD_HEAD, METHOD_OPTIONS, METHOD_PATCH, METHOD_POST, METHOD_PUT,
+};
+use crate::response::{Body, Response};
+use crate::{Handler, Router};
+
+// Headers about the connection rather than the message, which aren't forwarded (RFC 9110
+// 7.6.1). So are the headers named in Connection.
+const HOP_BY_HOP: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// Request headers the proxy writes itself, or drops in the case of Expect since the body follows
+// the head without waiting.
+const REPLACED: &[&str] = &[
+    "Host",
+    "Content-Length",
+    "Expect",
+    "X-Forwarded-For",
+    "X-Forwarded-Proto",
+    "X-Forwarded-Host",
+];
+
+// A handler forwarding requests to upstream servers and streaming their responses back:
+//
+//     let api = Proxy::new(&["10.0.0.1:8080", "10.0.0.2:8080"])
+//         .with_strip_prefix("/api")
+//         .with_health_check("/health", Duration::from_secs(10));
+//     router.add_proxy("/api/*path", api);
+//
+// Requests go to the upstreams in turn, skipping those found down, with X-Forwarded-For,
+// X-Forwarded-Proto and X-Forwarded-Host added. Upstreams speak plain HTTP over a connection
+// per request. Upgrades such as WebSocket aren't forwarded.
+#[derive(Clone)]
+pub struct Proxy {
+    upstreams: Arc<[Upstream]>,
+    next: Arc<AtomicUsize>,
+    strip_prefix: Option<String>,
+    preserve_host: bool,
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    health_checked: bool,
+    // The path and interval the health check thread reads before each round
+    health_check: Arc<Mutex<Option<(String, Duration)>>>,
+}
+
+struct Upstream {
+    addr: String,
+    healthy: AtomicBool,
+}
+
+impl Proxy {
+    // Upstreams as "host:port" or "http://host:port".
+    pub fn new(upstreams: &[&str]) -> Self {
+        assert!(!upstreams.is_empty(), "a proxy needs an upstream");
+        let upstreams: Vec<Upstream> = upstreams
+            .iter()
+            .map(|upstream| Upstream {
+                addr: parse_upstream(upstream),
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+        Proxy {
+            upstreams: upstreams.into(),
+            next: Arc::default(),
+            strip_prefix: None,
+            preserve_host: false,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Some(Duration::from_secs(60)),
+            health_checked: false,
+            health_check: Arc::default(),
+        }
+    }
+
+    // Removes a leading path segment or segments, so that "/api/users" is forwarded as
+    // "/users" with "/api".
+    pub fn with_strip_prefix(mut self, prefix: &str) -> Self {
+        self.strip_prefix = Some(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    // Whether upstreams get the Host of the request instead of their own address.
+    pub fn with_preserve_host(mut self, preserve_host: bool) -> Self {
+        self.preserve_host = preserve_host;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // How long reading from or writing to an upstream may block. Beyond this, 504.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Requests path from every upstream at each interval, in a thread that runs as long as the
+    // proxy. Upstreams not answering with 2xx or 3xx, or that refused a connection, get no
+    // requests until they do again. Calling it again changes the path and interval of the same
+    // thread from its next round.
+    pub fn with_health_check(mut self, path: &str, interval: Duration) -> Self {
+        let started = self
+            .health_check
+            .lock()
+            .unwrap()
+            .replace((path.to_string(), interval))
+            .is_some();
+        if !started {
+            let upstreams = Arc::downgrade(&self.upstreams);
+            let health_check = self.health_check.clone();
+            thread::spawn(move || check_health(upstreams, &health_check));
+        }
+        self.health_checked = true;
+        self
+    }
+
+    // Connects to the next healthy upstream, trying the others in turn if it's down.
+    fn connect(&self) -> Result<(&Upstream, TcpStream)> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut last_error = None;
+        for i in 0..self.upstreams.len() {
+            let upstream = &self.upstreams[(start + i) % self.upstreams.len()];
+            if !upstream.healthy.load(Ordering::Relaxed) {
+                continue;
+            }
+            match upstream.connect(self.connect_timeout) {
+                Ok(stream) => return Ok((upstream, stream)),
+                Err(e) => {
+                    // Until the next health check finds it up
+                    if self.health_checked {
+                        upstream.healthy.store(false, Ordering::Relaxed);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(match last_error {
+            Some(e) => upstream_error(e),
+            None => HttpError::new(503, "no healthy upstream").into(),
+        })
+    }
+
+    fn write_request_head<W: Write>(
+        &self,
+        conn: &Connection,
+        upstream: &Upstream,
+        writer: &mut W,
+    ) -> Result<()> {
+        let path = match &self.strip_prefix {
+            Some(prefix) => match conn.path.strip_prefix(prefix.as_str()) {
+                Some("") => "/",
+                Some(rest) if rest.starts_with('/') => rest,
+                _ => &conn.path,
+            },
+            None => &conn.path,
+        };
+        match &conn.query {
+            Some(query) => write!(writer, "{} {}?{} HTTP/1.1\r\n", conn.method, path, query)?,
+            None => write!(writer, "{} {} HTTP/1.1\r\n", conn.method, path)?,
+        }
+        let host = match conn.header("Host") {
+            Some(host) if self.preserve_host => host,
+            _ => &upstream.addr,
+        };
+        write!(writer, "Host: {}\r\n", host)?;
+
+        for (key, value) in conn.headers.iter() {
+            if !is_hop_by_hop(&conn.headers, key)
+                && !REPLACED
+                    .iter()
+                    .any(|replaced| replaced.eq_ignore_ascii_case(key))
+            {
+                write!(writer, "{}: {}\r\n", key, value)?;
+            }
+        }
+
+        let mut forwarded_for: Vec<String> = conn
+            .headers
+            .get_all("X-Forwarded-For")
+            .map(str::to_string)
+            .collect();
+        if let Some(addr) = conn.peer_addr() {
+            forwarded_for.push(addr.ip().to_string());
+        }
+        if !forwarded_for.is_empty() {
+            write!(writer, "X-Forwarded-For: {}\r\n", forwarded_for.join(", "))?;
+        }
+        let proto = if conn.is_secure() { "https" } else { "http" };
+        write!(writer, "X-Forwarded-Proto: {}\r\n", proto)?;
+        if let Some(host) = conn.header("Host") {
+            write!(writer, "X-Forwarded-Host: {}\r\n", host)?;
+        }
+
+        if conn.headers.contains("Transfer-Encoding") {
+            writer.write_all(b"Transfer-Encoding: chunked\r\n")?;
+        } else if let Some(len) = conn.header("Content-Length") {
+            write!(writer, "Content-Length: {}\r\n", len)?;
+        }
+        writer.write_all(b"Connection: close\r\n\r\n")
+    }
+
+    fn response<R: BufRead + Send + 'static>(
+        &self,
+        conn: &Connection,
+        status: u16,
+        reason: &str,
+        headers: Headers,
+        reader: R,
+    ) -> Result<Response> {
+        let mut response = Response::new(status);
+        if !reason.is_empty() {
+            response = response.with_reason(reason);
+        }
+        for (key, value) in headers.iter() {
+            if !is_hop_by_hop(&headers, key) {
+                response.append_header(key, value);
+            }
+        }
+        if conn.method == METHOD_HEAD || status == 204 || status == 304 {
+            return Ok(response);
+        }
+
+        let body: Box<dyn Read + Send> = if headers.contains("Transfer-Encoding") {
+            // Other codings can't be delimited here, or passed on to HTTP/1.0 clients
+            let mut codings = headers.get_all("Transfer-Encoding");
+            if !codings
+                .next()
+                .is_some_and(|c| c.trim().eq_ignore_ascii_case("chunked"))
+                || codings.next().is_some()
+            {
+                return Err(upstream_error(Error::new(
+                    ErrorKind::InvalidData,
+                    "unsupported Transfer-Encoding",
+                )));
+            }
+            response.remove_header("Content-Length");
+            Box::new(ChunkedReader::new(reader))
+        } else if let Some(len) = headers.get("Content-Length") {
+            let len = len.parse().map_err(|_| {
+                upstream_error(Error::new(ErrorKind::InvalidData, "invalid Content-Length"))
+            })?;
+            Box::new(ExactReader(reader.take(len)))
+        } else {
+            // Delimited by the upstream closing the connection
+            Box::new(reader)
+        };
+        Ok(response.with_body(Body::Stream(body)))
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        let (upstream, stream) = self.connect()?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        self.write_request_head(conn, upstream, &mut writer)
+            .map_err(upstream_error)?;
+        if conn.headers.contains("Transfer-Encoding") {
+            let mut chunked = ChunkedWriter::new(&mut writer);
+            copy_body(conn, &mut chunked)?;
+            chunked.finish().map_err(upstream_error)?;
+        } else {
+            copy_body(conn, &mut writer)?;
+        }
+        writer.flush().map_err(upstream_error)?;
+
+        let mut reader = BufReader::new(stream);
+        let limits = Limits::default();
+        loop {
+            let (status, reason, headers) =
+                read_response_head(&mut reader, &limits).map_err(upstream_error)?;
+            // Upgrades aren't forwarded, so a switch of protocols is the upstream's mistake
+            if status == 101 {
+                return Err(upstream_error(Error::new(
+                    ErrorKind::InvalidData,
+                    "unexpected 101 Switching Protocols",
+                )));
+            }
+            // Interim responses such as 100 Continue precede the final one
+            if status / 100 != 1 {
+                return self.response(conn, status, &reason, headers, reader);
+            }
+        }
+    }
+}
+
+impl Router {
+    // Forwards requests for path with the usual methods to proxy. HEAD requests follow GET,
+    // and CONNECT and TRACE aren't forwarded.
+    pub fn add_proxy(&mut self, path: &str, proxy: Proxy) -> &mut Self {
+        for method in [
+            METHOD_GET,
+            METHOD_POST,
+            METHOD_PUT,
+            METHOD_PATCH,
+            METHOD_DELETE,
+            METHOD_OPTIONS,
+        ] {
+            self.add_handler(method, path, proxy.clone());
+        }
+        self
+    }
+}
+
+impl Upstream {
+    fn connect(&self, timeout: Duration) -> Result<TcpStream> {
+        let mut last_error = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("no address for {}", self.addr))
+        }))
+    }
+
+    // The status of a GET request for path.
+    fn probe(&self, path: &str, timeout: Duration) -> Result<u16> {
+        let mut stream = self.connect(timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, self.addr
+        )?;
+        let (status, _, _) = read_response_head(&mut BufReader::new(stream), &Limits::default())?;
+        Ok(status)
+    }
+}
+
+fn check_health(upstreams: Weak<[Upstream]>, health_check: &Mutex<Option<(String, Duration)>>) {
+    while let Some(upstreams) = upstreams.upgrade() {
+        let (path, interval) = health_check.lock().unwrap().clone().unwrap();
+        for upstream in upstreams.iter() {
+            let healthy = upstream
+                .probe(&path, interval)
+                .is_ok_and(|status| (200..400).contains(&status));
+            upstream.healthy.store(healthy, Ordering::Relaxed);
+        }
+        drop(upstreams);
+        thread::sleep(interval);
+    }
+}
+
+// Reading the body fails with the error of the client, writing it with 502.
+fn copy_body<W: Write>(conn: &mut Connection, upstream: &mut W) -> Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let n = conn.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        upstream.write_all(&buf[..n]).map_err(upstream_error)?;
+    }
+}
+
+// A body cut short by the upstream fails instead of ending, so that the client can't mistake
+// it for a whole one.
+struct ExactReader<R>(Take<R>);
+
+impl<R: Read> Read for ExactReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.0.read(buf)?;
+        if n == 0 && !buf.is_empty() && self.0.limit() > 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "upstream closed in the middle of the body",
+            ));
+        }
+        Ok(n)
+    }
+}
+
+fn is_hop_by_hop(headers: &Headers, key: &str) -> bool {
+    HOP_BY_HOP
+        .iter()
+        .any(|hop_by_hop| hop_by_hop.eq_ignore_ascii_case(key))
+        || headers
+            .get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(key))
+}
+
+fn upstream_error(e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+            HttpError::new(504, &format!("upstream timed out: {}", e)).into()
+        }
+        _ => HttpError::new(502, &format!("upstream failed: {}", e)).into(),
+    }
+}
+
+fn parse_upstream(upstream: &str) -> String {
+    let addr = upstream
+        .strip_prefix("http://")
+        .unwrap_or(upstream)
+        .trim_end_matches('/');
+    assert!(
+        !addr.is_empty() && !addr.contains(['/', '?']) && !upstream.contains("s://"),
+        "upstream must be host:port or http://host:port, not {}",
+        upstream
+    );
+    if addr
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        addr.to_string()
+    } else {
+        format!("{}:80", addr)
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::http::Connection;
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+
+pub type RateLimitKey = Box<dyn Fn(&Connection) -> Option<String> + Send + Sync>;
+
+// Middleware limiting how often each client may make requests, answering 429 Too Many Requests
+// with a Retry-After header beyond the limit:
+//
+//     router.add_middleware(RateLimit::new(100, Duration::from_secs(60)).with_burst(20));
+//
+// Each client has a token bucket refilled at the given rate and holding at most the burst,
+// which defaults to the number of requests. Clients are told apart by IP address, which is the
+// proxy's behind a reverse proxy; with_key() can use a forwarded header or an API key instead.
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    key: RateLimitKey,
+    state: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    next_purge: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, per: Duration) -> Self {
+        assert!(
+            requests > 0 && !per.is_zero(),
+            "rate limit must be positive"
+        );
+        RateLimit {
+            rate: f64::from(requests) / per.as_secs_f64(),
+            burst: f64::from(requests),
+            key: Box::new(|conn| conn.peer_addr().map(|addr| addr.ip().to_string())),
+            state: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                next_purge: Instant::now(),
+            }),
+        }
+    }
+
+    // How many requests a client may make at once after being idle.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "burst must be positive");
+        self.burst = f64::from(burst);
+        self
+    }
+
+    // What identifies a client. Requests for which it returns None aren't limited.
+    pub fn with_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Connection) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Box::new(key);
+        self
+    }
+
+    // Takes a token from the bucket of the client, or returns how long until one is available.
+    fn acquire(&self, key: String) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if now >= state.next_purge {
+            self.purge(&mut state.buckets, now);
+            state.next_purge = now + Duration::from_secs_f64(self.burst / self.rate);
+        }
+
+        let bucket = state.buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    // Forgets the buckets that have refilled, which are the same as new ones.
+    fn purge(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * self.rate < self.burst
+        });
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let key = match (self.key)(conn) {
+            Some(key) => key,
+            None => return next.run(conn),
+        };
+        match self.acquire(key) {
+            Ok(()) => next.run(conn),
+            Err(wait) => {
+                let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Ok(Response::new(429)
+                    .with_header("Retry-After", &secs.to_string())
+                    .with_body("Too Many Requests"))
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::File;
+use std::io::{Read, Result, Write};
+use std::path::Path;
+
+use crate::http::{reason_phrase, Headers};
+
+pub type WriteBody = Box<dyn FnOnce(&mut dyn Write) -> Result<()> + Send>;
+
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    Stream(Box<dyn Read + Send>),
+    File(File),
+    Writer(WriteBody),
+}
+
+impl Body {
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream(_) | Body::Writer(_) => None,
+            Body::File(file) => file.metadata().ok().map(|m| m.len()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Body::Bytes(s.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(s: &str) -> Self {
+        Body::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<File> for Body {
+    fn from(file: File) -> Self {
+        Body::File(file)
+    }
+}
+
+pub struct Response {
+    status: u16,
+    reason: String,
+    headers: Headers,
+    body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            reason: reason_phrase(status).to_string(),
+            headers: Headers::new(),
+            body: Body::Empty,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200)
+    }
+
+    pub fn html<B: Into<String>>(body: B) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/html; charset=UTF-8")
+            .with_body(body.into())
+    }
+
+    pub fn json<B: Into<String>>(body: B) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "application/json; charset=UTF-8")
+            .with_body(body.into())
+    }
+
+    pub fn text<B: Into<String>>(body: B) -> Self {
+        Self::ok()
+            .with_header("Content-Type", "text/plain; charset=UTF-8")
+            .with_body(body.into())
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::new(status).with_header("Location", location)
+    }
+
+    pub fn stream<R: Read + Send + 'static>(reader: R) -> Self {
+        Self::ok().with_body(Body::Stream(Box::new(reader)))
+    }
+
+    pub fn streaming<F>(write_body: F) -> Self
+    where
+        F: FnOnce(&mut dyn Write) -> Result<()> + Send + 'static,
+    {
+        Self::ok().with_body(Body::Writer(Box::new(write_body)))
+    }
+
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::ok().with_body(File::open(path)?))
+    }
+
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = reason.to_string();
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.set_header(key, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Body>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn set_status(&mut self, status: u16) {
+        self.status = status;
+        self.reason = reason_phrase(status).to_string();
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key)
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.set(key, value);
+    }
+
+    pub fn append_header(&mut self, key: &str, value: &str) {
+        self.headers.append(key, value);
+    }
+
+    // Adds a header to Vary unless it is listed already.
+    pub fn add_vary(&mut self, key: &str) {
+        if !self.headers.has_token("Vary", key) && !self.headers.has_token("Vary", "*") {
+            self.headers.append("Vary", key);
+        }
+    }
+
+    pub fn remove_header(&mut self, key: &str) {
+        self.headers.remove(key);
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn set_body<B: Into<Body>>(&mut self, body: B) {
+        self.body = body.into();
+    }
+
+    pub fn take_body(&mut self) -> Body {
+        std::mem::replace(&mut self.body, Body::Empty)
+    }
+
+    pub(crate) fn into_parts(self) -> (u16, String, Headers, Body) {
+        (self.status, self.reason, self.headers, self.body)
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn new() -> Self {
+        Params { pairs: Vec::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T> {
+        let value = self.get(name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no path parameter named {}", name),
+            )
+        })?;
+        value.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("failed to parse path parameter {}={}", name, value),
+            )
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    fn push(&mut self, name: &str, value: String) {
+        self.pairs.push((name.to_string(), value));
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.pairs.truncate(len);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Int,
+    Uint,
+    Alpha,
+    Alnum,
+    Any,
+}
+
+impl Kind {
+    fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "int" => Some(Kind::Int),
+            "uint" => Some(Kind::Uint),
+            "alpha" => Some(Kind::Alpha),
+            "alnum" => Some(Kind::Alnum),
+            _ => None,
+        }
+    }
+
+    fn accepts(self, value: &str) -> bool {
+        if value.is_empty() {
+            return false;
+        }
+        match self {
+            Kind::Int => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+            }
+            Kind::Uint => value.bytes().all(|b| b.is_ascii_digit()),
+            Kind::Alpha => value.chars().all(char::is_alphabetic),
+            Kind::Alnum => value.chars().all(char::is_alphanumeric),
+            Kind::Any => true,
+        }
+    }
+}
+
+enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str, Kind),
+    Wildcard(&'a str),
+}
+
+fn parse_segment(segment: &str) -> Segment<'_> {
+    if let Some(name) = segment.strip_prefix('*') {
+        Segment::Wildcard(name)
+    } else if let Some(param) = segment.strip_prefix(':') {
+        match param.find('<') {
+            Some(i) if param.ends_with('>') => {
+                let kind_name = &param[i + 1..param.len() - 1];
+                let kind = Kind::from_name(kind_name)
+                    .unwrap_or_else(|| panic!("unknown parameter type <{}>", kind_name));
+                Segment::Param(&param[..i], kind)
+            }
+            _ => Segment::Param(param, Kind::Any),
+        }
+    } else {
+        Segment::Static(segment)
+    }
+}
+
+// Children are tried in order of priority: static segments, typed parameters, untyped parameters
+// and finally the trailing wildcard. The first complete match wins.
+pub struct Tree<T> {
+    statics: BTreeMap<String, Tree<T>>,
+    params: Vec<(Kind, String, Tree<T>)>,
+    wildcard: Option<(String, T)>,
+    value: Option<T>,
+}
+
+impl<T> Tree<T> {
+    pub fn new() -> Self {
+        Tree {
+            statics: BTreeMap::new(),
+            params: Vec::new(),
+            wildcard: None,
+            value: None,
+        }
+    }
+
+    pub(crate) fn get_or_insert_with<F: FnOnce() -> T>(&mut self, pattern: &str, f: F) -> &mut T {
+        let segments: Vec<&str> = split_path(pattern).collect();
+        let mut node = self;
+        for (i, segment) in segments.iter().enumerate() {
+            match parse_segment(segment) {
+                Segment::Static(s) => {
+                    node = node.statics.entry(s.to_string()).or_default();
+                }
+                Segment::Param(name, kind) => {
+                    let pos = match node.params.iter().position(|(k, _, _)| *k == kind) {
+                        Some(pos) => {
+                            if node.params[pos].1 != name {
+                                panic!(
+                                    "route {} conflicts with parameter :{} at the same position",
+                                    pattern, node.params[pos].1
+                                );
+                            }
+                            pos
+                        }
+                        None => {
+                            node.params.push((kind, name.to_string(), Tree::new()));
+                            node.params.sort_by_key(|(k, _, _)| *k);
+                            node.params.iter().position(|(k, _, _)| *k == kind).unwrap()
+                        }
+                    };
+                    node = &mut node.params[pos].2;
+                }
+                Segment::Wildcard(name) => {
+                    if i != segments.len() - 1 {
+                        panic!("wildcard must be the last segment of route {}", pattern);
+                    }
+                    if let Some((existing, _)) = &node.wildcard {
+                        if existing != name {
+                            panic!(
+                                "route {} conflicts with wildcard *{} at the same position",
+                                pattern, existing
+                            );
+                        }
+                    }
+                    let wildcard = node.wildcard.get_or_insert_with(|| (name.to_string(), f()));
+                    return &mut wildcard.1;
+                }
+            }
+        }
+        node.value.get_or_insert_with(f)
+    }
+
+    pub fn insert(&mut self, pattern: &str, value: T) {
+        let mut value = Some(value);
+        let slot = self.get_or_insert_with(pattern, || value.take().unwrap());
+        if let Some(value) = value {
+            *slot = value;
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<(&T, Params)> {
+        let segments: Vec<&str> = split_path(path).collect();
+        let mut params = Params::new();
+        self.find_segments(&segments, &mut params)
+            .map(|value| (value, params))
+    }
+
+    fn find_segments(&self, segments: &[&str], params: &mut Params) -> Option<&T> {
+        let (head, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                return self
+                    .value
+                    .as_ref()
+                    .or_else(|| self.find_wildcard(segments, params))
+            }
+        };
+
+        if let Some(child) = self.statics.get(*head) {
+            if let Some(value) = child.find_segments(rest, params) {
+                return Some(value);
+            }
+        }
+
+        let decoded = percent_decode(head);
+        for (kind, name, child) in &self.params {
+            if !kind.accepts(&decoded) {
+                continue;
+            }
+            let len = params.len();
+            params.push(name, decoded.clone());
+            if let Some(value) = child.find_segments(rest, params) {
+                return Some(value);
+            }
+            params.truncate(len);
+        }
+
+        self.find_wildcard(segments, params)
+    }
+
+    fn find_wildcard(&self, segments: &[&str], params: &mut Params) -> Option<&T> {
+        let (name, value) = self.wildcard.as_ref()?;
+        let rest: Vec<String> = segments.iter().map(|s| percent_decode(s)).collect();
+        params.push(name, rest.join("/"));
+        Some(value)
+    }
+}
+
+impl<T> Default for Tree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+pub(crate) fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::http::{error_status, Connection};
+use crate::pool::WorkerPool;
+use crate::response::Response;
+use crate::sse::is_disconnect;
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::Router;
+
+pub struct Server {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown_timeout: Duration,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            addr: self.addr,
+            state: self.state.clone(),
+        }
+    }
+
+    // Stops the server when the process receives SIGTERM or SIGINT.
+    #[cfg(unix)]
+    pub fn stop_on_signals(&self) -> Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let handle = self.handle();
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                handle.stop();
+            }
+        });
+        Ok(())
+    }
+
+    // Blocks until the server is stopped through a ServerHandle or a signal, then drains it.
+    pub fn wait(self) -> Result<()> {
+        let mut connections = self.state.lock();
+        while !connections.stopping {
+            connections = self.state.wait(connections);
+        }
+        drop(connections);
+        self.drain()
+    }
+
+    pub fn shutdown(self) -> Result<()> {
+        self.handle().stop();
+        self.drain()
+    }
+
+    // Lets in-flight requests finish and closes idle keep-alive connections. Connections still
+    // busy when the shutdown timeout expires are closed forcibly. Either way the accept thread,
+    // already signalled to stop, is joined before returning.
+    fn drain(mut self) -> Result<()> {
+        let deadline = Instant::now() + self.shutdown_timeout;
+        let mut connections = self.state.lock();
+        let mut result = Ok(());
+        while !connections.sockets.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                let remaining = connections.sockets.len();
+                for (socket, _) in connections.sockets.values() {
+                    let _ = socket.shutdown(Shutdown::Both);
+                }
+                result = Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "{} connections still open after shutdown timeout",
+                        remaining
+                    ),
+                ));
+                break;
+            }
+            connections = self.state.wait_timeout(connections, deadline - now);
+        }
+        drop(connections);
+
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+        result
+    }
+}
+
+#[derive(Clone)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    state: Arc<State>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn stop(&self) {
+        let mut connections = self.state.lock();
+        if connections.stopping {
+            return;
+        }
+        connections.stopping = true;
+        self.state.shutdown.set();
+        for (socket, idle) in connections.sockets.values() {
+            if *idle {
+                let _ = socket.shutdown(Shutdown::Both);
+            }
+        }
+        self.state.changed.notify_all();
+        drop(connections);
+
+        // Wake the accept loop up so that it notices the server is stopping
+        let _ = TcpStream::connect(wake_addr(self.addr));
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.lock().stopping
+    }
+}
+
+struct Connections {
+    stopping: bool,
+    next_id: usize,
+    sockets: HashMap<usize, (TcpStream, bool)>,
+}
+
+struct State {
+    connections: Mutex<Connections>,
+    changed: Condvar,
+    shutdown: ShutdownSignal,
+}
+
+impl State {
+    fn lock(&self) -> MutexGuard<'_, Connections> {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, Connections>) -> MutexGuard<'a, Connections> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait_timeout<'a>(
+        &self,
+        guard: MutexGuard<'a, Connections>,
+        timeout: Duration,
+    ) -> MutexGuard<'a, Connections> {
+        match self.changed.wait_timeout(guard, timeout) {
+            Ok((guard, _)) => guard,
+            Err(e) => e.into_inner().0,
+        }
+    }
+
+    fn register(self: &Arc<Self>, stream: &TcpStream) -> Result<Tracked> {
+        let socket = stream.try_clone()?;
+        let mut connections = self.lock();
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.sockets.insert(id, (socket, false));
+        Ok(Tracked {
+            id,
+            state: self.clone(),
+        })
+    }
+}
+
+// Set once the server starts shutting down, so that long-running responses such as event
+// streams can end instead of holding the shutdown up, see Connection::shutdown_signal().
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+impl ShutdownSignal {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
+// Keeps a connection in the server's registry for as long as it is alive.
+pub(crate) struct Tracked {
+    id: usize,
+    state: Arc<State>,
+}
+
+impl Tracked {
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.state.lock().stopping
+    }
+
+    // Marks the connection as waiting for its next request, which a shutdown may interrupt.
+    // Returns false if the server is already stopping.
+    pub(crate) fn set_idle(&self, idle: bool) -> bool {
+        let mut connections = self.state.lock();
+        if idle && connections.stopping {
+            return false;
+        }
+        if let Some(entry) = connections.sockets.get_mut(&self.id) {
+            entry.1 = idle;
+        }
+        true
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.state.lock().sockets.remove(&self.id);
+        self.state.changed.notify_all();
+    }
+}
+
+impl Router {
+    // Binds to addr and serves connections in the background. Port 0 picks a free port, which
+    // local_addr() reports.
+    pub fn bind(self, addr: &str) -> Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State {
+            connections: Mutex::new(Connections {
+                stopping: false,
+                next_id: 0,
+                sockets: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            shutdown: ShutdownSignal::default(),
+        });
+        let shutdown_timeout = self.shutdown_timeout;
+
+        let accept_thread = {
+            let state = state.clone();
+            std::thread::spawn(move || accept_loop(Arc::new(self), listener, state))
+        };
+
+        Ok(Server {
+            addr,
+            state,
+            shutdown_timeout,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    fn handle_client(&self, stream: TcpStream, tracked: &Tracked) -> Result<()> {
+        let mut conn = self.open_connection(stream)?;
+        conn.set_shutdown_signal(tracked.state.shutdown.clone());
+        conn.set_limits(self.limits);
+        conn.set_header_timeout(self.header_timeout);
+        conn.set_write_timeout(self.write_timeout)?;
+        conn.set_read_timeout(self.keep_alive_timeout)?;
+        // Waiting for the first request is idle too, so that a shutdown needn't wait for it.
+        // Connections queued before a shutdown still get theirs read.
+        tracked.set_idle(true);
+        let first = conn.next_request();
+        tracked.set_idle(false);
+        match first {
+            Ok(true) => conn.set_read_timeout(self.read_timeout)?,
+            Ok(false) => return Ok(()),
+            Err(e) => return send_error(&mut conn, e),
+        }
+        self.serve_connection(conn, tracked)
+    }
+
+    fn open_connection(&self, stream: TcpStream) -> Result<Connection> {
+        #[cfg(feature = "tls")]
+        {
+            if let Some(config) = &self.tls {
+                let socket = stream.try_clone()?;
+                let (reader, writer) = tls::split(config.clone(), stream)?;
+                let mut conn = Connection::open(Box::new(reader), Box::new(writer), Some(socket));
+                conn.set_secure(true);
+                return Ok(conn);
+            }
+        }
+        Connection::new(stream)
+    }
+
+    fn serve_connection(&self, mut conn: Connection, tracked: &Tracked) -> Result<()> {
+        let mut requests = 0;
+        loop {
+            requests += 1;
+            if requests >= self.max_requests_per_connection {
+                conn.set_keep_alive(false);
+            }
+
+            let response = match self.dispatch(&mut conn) {
+                Ok(response) => response,
+                Err(e) => return send_error(&mut conn, e),
+            };
+            if tracked.is_stopping() {
+                conn.set_keep_alive(false);
+            }
+            conn.send(response)?;
+
+            if !conn.keep_alive() || !tracked.set_idle(true) {
+                return Ok(());
+            }
+            conn.set_read_timeout(self.keep_alive_timeout)?;
+            let next = conn.next_request();
+            tracked.set_idle(false);
+            match next {
+                Ok(true) => conn.set_read_timeout(self.read_timeout)?,
+                Ok(false) => return Ok(()),
+                Err(e) => return send_error(&mut conn, e),
+            }
+        }
+    }
+}
+
+fn accept_loop(router: Arc<Router>, listener: TcpListener, state: Arc<State>) {
+    let error_log = router.error_log.clone();
+    let max_connections = router.max_connections;
+    #[cfg(feature = "tls")]
+    let tls = router.tls.is_some();
+    #[cfg(not(feature = "tls"))]
+    let tls = false;
+    let pool = WorkerPool::new(
+        router.worker_threads,
+        router.queue_size,
+        move |(stream, tracked): (TcpStream, Tracked)| {
+            if let Err(e) = router.handle_client(stream, &tracked) {
+                if !is_idle_error(&e) && !is_disconnect(&e) {
+                    router.error_log.log(&e.to_string());
+                }
+            }
+        },
+    );
+
+    for stream in listener.incoming() {
+        if state.lock().stopping {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error_log.log(&e.to_string());
+                continue;
+            }
+        };
+        if state.lock().sockets.len() >= max_connections {
+            if let Err(e) = reject_client(stream, tls) {
+                error_log.log(&e.to_string());
+            }
+            continue;
+        }
+        let tracked = match state.register(&stream) {
+            Ok(tracked) => tracked,
+            Err(e) => {
+                error_log.log(&e.to_string());
+                continue;
+            }
+        };
+        if let Err((stream, _)) = pool.submit((stream, tracked)) {
+            if let Err(e) = reject_client(stream, tls) {
+                error_log.log(&e.to_string());
+            }
+        }
+    }
+
+    // Serves what is already queued, with keep-alive disabled, before returning
+    pool.join();
+}
+
+// Answers a request that failed with an http::HttpError, such as a malformed request or a body
+// over the size limit, and closes the connection. Other errors are passed on.
+pub(crate) fn send_error(conn: &mut Connection, e: Error) -> Result<()> {
+    match error_status(&e) {
+        Some(status) if !conn.is_header_finished() => {
+            conn.set_keep_alive(false);
+            conn.send(Response::new(status))
+        }
+        _ if is_idle_error(&e) => Ok(()),
+        _ => Err(e),
+    }
+}
+
+// TLS clients get the connection closed instead, since answering them would take a handshake.
+fn reject_client(mut stream: TcpStream, tls: bool) -> Result<()> {
+    if tls {
+        return Ok(());
+    }
+    stream.write_all(
+        b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    )
+}
+
+// Errors of a connection closed while it was waiting for a request, by the client, the idle
+// timeout or a shutdown.
+pub(crate) fn is_idle_error(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::UnexpectedEof
+    )
+}
+
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port())
+        }
+        _ => addr,
+    }
+}
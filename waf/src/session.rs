@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+
+use crate::cookie::{Cookie, SameSite};
+use crate::http::Connection;
+use crate::middleware::{Middleware, Next};
+use crate::response::Response;
+
+pub type SessionData = HashMap<String, String>;
+
+// Where sessions are kept between requests. A session not saved again within its time to live
+// is gone.
+pub trait SessionStore: Send + Sync + 'static {
+    fn load(&self, id: &str) -> Result<Option<SessionData>>;
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()>;
+    fn remove(&self, id: &str) -> Result<()>;
+}
+
+// Sessions in memory, lost on restart and not shared between processes.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, SystemTime)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        match sessions.get(id) {
+            Some((data, expires)) if *expires > SystemTime::now() => Ok(Some(data.clone())),
+            Some(_) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()> {
+        let now = SystemTime::now();
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, (_, expires)| *expires > now);
+        sessions.insert(id.to_string(), (data.clone(), now + ttl));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.remove(id);
+        Ok(())
+    }
+}
+
+// One JSON file per session in a directory, named after the session ID. Expired files are
+// deleted when they are next loaded.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        if !is_valid_session_id(id) {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid session ID"));
+        }
+        Ok(self.dir.join(id))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>> {
+        let path = self.path(id)?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let invalid = |e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid session file: {}", e),
+            )
+        };
+        let mut file: Value = serde_json::from_str(&contents).map_err(invalid)?;
+        let expires = file["expires"]
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid session file"))?;
+        if UNIX_EPOCH + Duration::from_secs(expires) <= SystemTime::now() {
+            let _ = fs::remove_file(&path);
+            return Ok(None);
+        }
+        let data = serde_json::from_value(file["data"].take()).map_err(invalid)?;
+        Ok(Some(data))
+    }
+
+    // Written to a temporary file first so that a concurrent load never sees half a session.
+    // Each save has a temporary file of its own, so that concurrent saves of a session don't
+    // write into each other's.
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()> {
+        static SAVES: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(id)?;
+        let expires = (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .map_err(Error::other)?;
+        let contents = json!({ "expires": expires.as_secs(), "data": data }).to_string();
+        let tmp = self.dir.join(format!(
+            "{}.{}-{}.tmp",
+            id,
+            process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The session of the current request, available to handlers behind the Sessions middleware:
+//
+//     let session = conn.session().unwrap();
+//     session.set("user", "alice");
+pub struct Session {
+    id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    renew: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new(id: Option<String>, data: SessionData) -> Self {
+        Session {
+            id,
+            data,
+            changed: false,
+            renew: false,
+            destroyed: false,
+        }
+    }
+
+    // None until the session is first saved.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_string(), value.to_string());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.data.remove(key);
+        self.changed |= value.is_some();
+        value
+    }
+
+    pub fn clear(&mut self) {
+        self.changed |= !self.data.is_empty();
+        self.data.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Moves the session to a new ID, keeping its data. Call it when the user logs in so that an
+    // ID planted before can't be used to hijack the session.
+    pub fn renew(&mut self) {
+        self.renew = true;
+    }
+
+    // Deletes the session from the store and the client.
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+}
+
+impl Connection {
+    // None unless the Sessions middleware runs for this request.
+    pub fn session(&mut self) -> Option<&mut Session> {
+        self.extensions_mut().get_mut::<Session>()
+    }
+}
+
+// Middleware loading the session named by a cookie before the handler runs and saving it
+// afterwards:
+//
+//     router.add_middleware(Sessions::new(MemoryStore::new()).with_secure(true));
+//
+// A session is created when data is first set in it. Sessions in use expire after the time to
+// live of inactivity, 24 hours by default. Session IDs are 256 random bits, so the cookie
+// needn't be signed.
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    path: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    pub fn new<S: SessionStore>(store: S) -> Self {
+        Sessions {
+            store: Arc::new(store),
+            cookie_name: "session".to_string(),
+            path: "/".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn with_cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // Whether the cookie is only sent over HTTPS.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn load(&self, conn: &Connection) -> Result<Session> {
+        if let Some(id) = conn.cookie(&self.cookie_name) {
+            if is_valid_session_id(&id) {
+                if let Some(data) = self.store.load(&id)? {
+                    return Ok(Session::new(Some(id), data));
+                }
+            }
+        }
+        Ok(Session::new(None, SessionData::new()))
+    }
+
+    fn save(&self, session: Session, response: &mut Response) -> Result<()> {
+        if session.destroyed {
+            if let Some(id) = &session.id {
+                self.store.remove(id)?;
+                response.set_cookie(&self.cookie("").with_max_age(Duration::from_secs(0)));
+            }
+            return Ok(());
+        }
+
+        let id = match session.id {
+            Some(id) if session.renew => {
+                self.store.remove(&id)?;
+                generate_session_id()?
+            }
+            Some(id) => id,
+            None if session.changed && !session.data.is_empty() => generate_session_id()?,
+            None => return Ok(()),
+        };
+        self.store.save(&id, &session.data, self.ttl)?;
+        response.set_cookie(&self.cookie(&id).with_max_age(self.ttl));
+        Ok(())
+    }
+
+    fn cookie(&self, id: &str) -> Cookie {
+        Cookie::new(&self.cookie_name, id)
+            .with_path(&self.path)
+            .with_http_only(true)
+            .with_secure(self.secure)
+            .with_same_site(self.same_site)
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, conn: &mut Connection, next: Next<'_>) -> Result<Response> {
+        let session = self.load(conn)?;
+        conn.extensions_mut().insert(session);
+        let mut response = next.run(conn)?;
+        if let Some(session) = conn.extensions_mut().remove::<Session>() {
+            self.save(session, &mut response)?;
+        }
+        Ok(response)
+    }
+}
+
+fn generate_session_id() -> Result<String> {
+    let mut bytes = [0; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| Error::other("no random source"))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn is_valid_session_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::http::Connection;
+use crate::response::{Body, Response};
+
+// How often an event channel checks whether the server is shutting down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+// One server-sent event:
+//
+//     Event::new("{\"price\": 42}").with_event("quote").with_id("17")
+//
+// Data spanning lines is sent as one data field per line, which the browser joins again. An
+// event without data only updates the last event ID or the retry delay of the client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Event {
+            data: data.to_string(),
+            ..Event::default()
+        }
+    }
+
+    // The type of the event, dispatched to listeners added with addEventListener() instead of
+    // onmessage.
+    pub fn with_event(mut self, event: &str) -> Self {
+        assert!(is_field_value(event), "invalid event type: {:?}", event);
+        self.event = Some(event.to_string());
+        self
+    }
+
+    // Sent back by a reconnecting browser as Last-Event-ID.
+    pub fn with_id(mut self, id: &str) -> Self {
+        assert!(
+            is_field_value(id) && !id.contains('\0'),
+            "invalid event ID: {:?}",
+            id
+        );
+        self.id = Some(id.to_string());
+        self
+    }
+
+    // How long the browser waits before reconnecting after the stream is lost.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    pub fn event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+}
+
+// The event in the text/event-stream format, blank line included.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        if !self.data.is_empty() {
+            for line in self
+                .data
+                .split("\r\n")
+                .flat_map(|line| line.split(['\r', '\n']))
+            {
+                writeln!(f, "data: {}", line)?;
+            }
+        }
+        writeln!(f)
+    }
+}
+
+// Writes events to the client of an event stream, each flushed as soon as it's sent. Writing
+// fails once the client has disconnected, see is_disconnect().
+pub struct EventSender<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl EventSender<'_> {
+    pub fn send(&mut self, event: &Event) -> Result<()> {
+        self.writer.write_all(event.to_string().as_bytes())?;
+        self.writer.flush()
+    }
+
+    // A comment, ignored by the browser. Sent periodically, comments keep proxies from closing
+    // an idle stream and find out when the client is gone.
+    pub fn comment(&mut self, comment: &str) -> Result<()> {
+        for line in comment.lines() {
+            writeln!(self.writer, ": {}", line)?;
+        }
+        if comment.is_empty() {
+            self.writer.write_all(b":\n")?;
+        }
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.comment("")
+    }
+}
+
+impl Response {
+    // A text/event-stream response whose events are written by f until it returns:
+    //
+    //     Ok(Response::event_stream(move |events| {
+    //         for quote in quotes {
+    //             events.send(&Event::new(&quote.to_json()))?;
+    //         }
+    //         Ok(())
+    //     }))
+    //
+    // The stream holds a worker thread, or a thread of the async server's blocking pool, until
+    // it ends. Long-lived streams should end when conn.shutdown_signal() is set, or the server
+    // waits for them until its shutdown timeout.
+    pub fn event_stream<F>(f: F) -> Self
+    where
+        F: FnOnce(&mut EventSender<'_>) -> Result<()> + Send + 'static,
+    {
+        Self::ok()
+            .with_header("Content-Type", "text/event-stream")
+            // no-transform keeps proxies and the Compression middleware from buffering events
+            .with_header("Cache-Control", "no-cache, no-transform")
+            .with_header("X-Accel-Buffering", "no")
+            .with_body(Body::Writer(Box::new(move |writer| {
+                f(&mut EventSender { writer })
+            })))
+    }
+
+    // An event stream sending the events received from a channel, and a heartbeat comment
+    // whenever none came for the given interval. The stream ends when every sender is dropped
+    // or the server serving conn shuts down, and the receiver is dropped when the client
+    // disconnects, which makes sending fail.
+    pub fn event_channel(conn: &Connection, events: Receiver<Event>, heartbeat: Duration) -> Self {
+        let shutdown = conn.shutdown_signal();
+        Self::event_stream(move |sender| {
+            let mut last_sent = Instant::now();
+            while !shutdown.is_set() {
+                let wait = heartbeat.saturating_sub(last_sent.elapsed());
+                match events.recv_timeout(wait.min(SHUTDOWN_POLL)) {
+                    Ok(event) => sender.send(&event)?,
+                    Err(RecvTimeoutError::Timeout) if wait > SHUTDOWN_POLL => continue,
+                    Err(RecvTimeoutError::Timeout) => sender.heartbeat()?,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                last_sent = Instant::now();
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Connection {
+    // The ID of the last event a reconnecting client received, to resume the stream after it.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.header("Last-Event-ID").filter(|id| !id.is_empty())
+    }
+}
+
+// Errors of writing to a client that has closed the connection.
+pub fn is_disconnect(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
+
+fn is_field_value(value: &str) -> bool {
+    !value.contains(['\r', '\n'])
+}
@@ -0,0 +1,271 @@
+use std::fs::{self, File, Metadata};
+use std::io::{ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use chrono::{DateTime, Utc};
+
+use crate::cache::Validators;
+use crate::http::Connection;
+use crate::response::{Body, Response};
+use crate::template::escape_html;
+use crate::Handler;
+
+// Serves files below root, named by the trailing wildcard of the route:
+//
+//     router.add_handler(METHOD_GET, "/assets/*path", StaticFiles::new("public"));
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+}
+
+impl StaticFiles {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        StaticFiles {
+            root: root.as_ref().to_path_buf(),
+            index: Some("index.html".to_string()),
+            listing: false,
+        }
+    }
+
+    pub fn with_index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(|s| s.to_string());
+        self
+    }
+
+    pub fn with_listing(mut self, listing: bool) -> Self {
+        self.listing = listing;
+        self
+    }
+
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for component in Path::new(relative).components() {
+            match component {
+                Component::Normal(name) => path.push(name),
+                Component::CurDir => {}
+                // "..", "/" and drive prefixes could escape the root
+                _ => return None,
+            }
+        }
+
+        // Symbolic links could still point outside of the root
+        let root = self.root.canonicalize().ok()?;
+        let path = path.canonicalize().ok()?;
+        if path.starts_with(&root) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn serve_directory(&self, conn: &Connection, dir: &Path, relative: &str) -> Result<Response> {
+        if !conn.path.ends_with('/') {
+            return Ok(Response::redirect(301, &format!("{}/", conn.path)));
+        }
+        if let Some(index) = &self.index {
+            let index = dir.join(index);
+            if index.is_file() {
+                return serve_file(conn, &index);
+            }
+        }
+        if self.listing {
+            return list_directory(dir, relative);
+        }
+        Ok(Response::new(404))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        let relative = conn.params.iter().last().map_or("", |(_, v)| v).to_string();
+        if relative.contains('\0') || relative.contains('\\') {
+            return Ok(Response::new(404));
+        }
+        let path = match self.resolve(&relative) {
+            Some(path) => path,
+            None => return Ok(Response::new(404)),
+        };
+        if path.is_dir() {
+            self.serve_directory(conn, &path, &relative)
+        } else {
+            serve_file(conn, &path)
+        }
+    }
+}
+
+fn serve_file(conn: &Connection, path: &Path) -> Result<Response> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Response::new(404)),
+        Err(ref e) if e.kind() == ErrorKind::PermissionDenied => return Ok(Response::new(403)),
+        Err(e) => return Err(e),
+    };
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+    let mut validators = Validators::new().with_etag(&etag(&metadata));
+    if let Some(last_modified) = modified(&metadata) {
+        validators = validators.with_last_modified(last_modified);
+    }
+
+    if let Some(status) = validators.evaluate(&conn.method, &conn.headers) {
+        return Ok(validators.response(status));
+    }
+
+    let mut response = Response::ok()
+        .with_header("Content-Type", mime_type(path))
+        .with_header("Accept-Ranges", "bytes");
+    validators.set_headers(&mut response);
+
+    let range = match conn.header("Range") {
+        Some(range) if validators.if_range(&conn.headers) => parse_range(range, len),
+        _ => RangeSpec::Full,
+    };
+    match range {
+        RangeSpec::Full => Ok(response.with_body(file)),
+        RangeSpec::Unsatisfiable => {
+            response.set_status(416);
+            response.set_header("Content-Range", &format!("bytes */{}", len));
+            Ok(response)
+        }
+        RangeSpec::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start))?;
+            response.set_status(206);
+            response.set_header("Content-Range", &format!("bytes {}-{}/{}", start, end, len));
+            response.set_header("Content-Length", &(end - start + 1).to_string());
+            Ok(response.with_body(Body::Stream(Box::new(file.take(end - start + 1)))))
+        }
+    }
+}
+
+fn list_directory(dir: &Path, relative: &str) -> Result<Response> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() {
+                format!("{}/", name)
+            } else {
+                name
+            }
+        })
+        .collect();
+    names.sort();
+
+    let title = escape_html(&format!("/{}", relative));
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if !relative.is_empty() {
+        body.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in names {
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            encode_href(&name),
+            escape_html(&name)
+        ));
+    }
+    body.push_str("</ul>\n</body>\n</html>\n");
+    Ok(Response::html(body))
+}
+
+// Percent-encodes a listed name for a relative link, so that names with '%', '?', '#' or ':'
+// link to themselves. The '/' marking a directory is kept.
+fn encode_href(name: &str) -> String {
+    let (name, slash) = match name.strip_suffix('/') {
+        Some(name) => (name, "/"),
+        None => (name, ""),
+    };
+    let mut encoded = String::with_capacity(name.len() + slash.len());
+    for b in name.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded.push_str(slash);
+    encoded
+}
+
+enum RangeSpec {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Only a single byte range is supported. Anything else is answered with the full content,
+// which RFC 9110 allows.
+fn parse_range(range: &str, len: u64) -> RangeSpec {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return RangeSpec::Full,
+    };
+    let (start, end) = match spec.find('-') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => return RangeSpec::Full,
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return RangeSpec::Full,
+    };
+    if start < len && start <= end {
+        RangeSpec::Partial(start, end)
+    } else {
+        RangeSpec::Unsatisfiable
+    }
+}
+
+fn etag(metadata: &Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", metadata.len(), mtime)
+}
+
+fn modified(metadata: &Metadata) -> Option<DateTime<Utc>> {
+    metadata.modified().ok().map(DateTime::<Utc>::from)
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=UTF-8",
+        Some("css") => "text/css; charset=UTF-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=UTF-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=UTF-8",
+        Some("csv") => "text/csv; charset=UTF-8",
+        Some("xml") => "application/xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("wasm") => "application/wasm",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::response::Response;
+
+// How deep templates may nest through include and extends, which stops cycles.
+const MAX_DEPTH: usize = 16;
+
+// HTML templates loaded from a directory:
+//
+//     router.add_state(Templates::new("templates"));
+//     router.add_handler(METHOD_GET, "/", handler(|State(templates): State<Templates>| {
+//         templates.response("index.html", &json!({ "user": "alice", "items": [1, 2] }))
+//     }));
+//
+// with templates such as:
+//
+//     {% extends "layout.html" %}
+//     {% block content %}
+//       <h1>Hello, {{ user }}</h1>
+//       {% for item in items %}<p>{{ loop.index }}: {{ item }}</p>{% else %}<p>None</p>{% endfor %}
+//       {% if user == "admin" %}{% include "admin.html" %}{% endif %}
+//     {% endblock %}
+//
+// {{ }} outputs a value HTML-escaped unless followed by | raw; other filters are upper, lower and
+// length. Conditions can use ==, !=, not, and, or and parentheses. Values missing from the
+// context are empty. {# #} is a comment.
+//
+// Templates are parsed when first used and cached. With reloading, the default in debug
+// builds, a template whose file has changed is parsed again.
+pub struct Templates {
+    dir: PathBuf,
+    reload: bool,
+    cache: RwLock<HashMap<String, Cached>>,
+}
+
+struct Cached {
+    template: Arc<Template>,
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Templates {
+            dir: dir.into(),
+            reload: cfg!(debug_assertions),
+            cache: RwLock::default(),
+        }
+    }
+
+    pub fn with_reload(mut self, reload: bool) -> Self {
+        self.reload = reload;
+        self
+    }
+
+    // Renders the template at name, a path relative to the directory, with the fields of
+    // context as variables.
+    pub fn render<T: Serialize>(&self, name: &str, context: &T) -> Result<String> {
+        let context = serde_json::to_value(context).map_err(Error::other)?;
+        let mut render = Render {
+            templates: self,
+            root: &context,
+            scopes: Vec::new(),
+            blocks: HashMap::new(),
+            depth: 0,
+            out: String::new(),
+        };
+        render.template(name)?;
+        Ok(render.out)
+    }
+
+    // A text/html response with the rendered template.
+    pub fn response<T: Serialize>(&self, name: &str, context: &T) -> Result<Response> {
+        Ok(Response::html(self.render(name, context)?))
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<Template>> {
+        let path = self.path(name)?;
+        let modified = if self.reload {
+            Some(fs::metadata(&path)?.modified()?)
+        } else {
+            None
+        };
+        let cache = self.cache.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = cache.get(name) {
+            if cached.modified == modified {
+                return Ok(cached.template.clone());
+            }
+        }
+        drop(cache);
+
+        let template = Arc::new(Template::parse(name, &fs::read_to_string(&path)?)?);
+        let mut cache = self.cache.write().unwrap_or_else(|e| e.into_inner());
+        cache.insert(
+            name.to_string(),
+            Cached {
+                template: template.clone(),
+                modified,
+            },
+        );
+        Ok(template)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        if name.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid template name {}", name),
+            ));
+        }
+        Ok(self.dir.join(relative))
+    }
+}
+
+struct Template {
+    extends: Option<String>,
+    nodes: Vec<Node>,
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+}
+
+enum Node {
+    Text(String),
+    Output(Expr, Vec<Filter>),
+    If(Vec<(Expr, Vec<Node>)>, Vec<Node>),
+    For {
+        key: Option<String>,
+        value: String,
+        iterable: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Include(String),
+    // Its content is in Template::blocks, so that templates extending this one can replace it
+    Block(String),
+}
+
+enum Expr {
+    Path(Vec<String>),
+    Literal(Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+}
+
+enum Filter {
+    Raw,
+    Upper,
+    Lower,
+    Length,
+}
+
+impl Template {
+    fn parse(name: &str, source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            name,
+            tokens: tokenize(name, source)?.into_iter(),
+            extends: None,
+            blocks: HashMap::new(),
+        };
+        let (nodes, _) = parser.nodes(&[])?;
+        Ok(Template {
+            extends: parser.extends,
+            nodes,
+            blocks: parser.blocks,
+        })
+    }
+}
+
+enum Token {
+    Text(String),
+    Output(String, usize),
+    Tag(String, usize),
+}
+
+fn tokenize(name: &str, source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    loop {
+        let start = match ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min()
+        {
+            Some(start) => start,
+            None => {
+                if !rest.is_empty() {
+                    tokens.push(Token::Text(rest.to_string()));
+                }
+                return Ok(tokens);
+            }
+        };
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        line += rest[..start].matches('\n').count();
+        let close = match &rest[start..start + 2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let len = rest[start + 2..].find(close).ok_or_else(|| {
+            syntax_error(
+                name,
+                line,
+                &format!("{} is never closed", &rest[start..start + 2]),
+            )
+        })?;
+        let inside = &rest[start + 2..start + 2 + len];
+        match close {
+            "}}" => tokens.push(Token::Output(inside.trim().to_string(), line)),
+            "%}" => tokens.push(Token::Tag(inside.trim().to_string(), line)),
+            _ => {}
+        }
+        line += inside.matches('\n').count();
+        rest = &rest[start + 2 + len + 2..];
+    }
+}
+
+struct Parser<'a> {
+    name: &'a str,
+    tokens: std::vec::IntoIter<Token>,
+    extends: Option<String>,
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+}
+
+// The tag that ended a list of nodes, split into its keyword and the rest, and its line.
+type EndTag = (String, String, usize);
+
+impl Parser<'_> {
+    // Parses nodes until one of the tags in ends, which is returned. The last one closes the
+    // enclosing tag, and is reported missing at the end of the template.
+    fn nodes(&mut self, ends: &[&str]) -> Result<(Vec<Node>, EndTag)> {
+        let mut nodes = Vec::new();
+        while let Some(token) = self.tokens.next() {
+            let (tag, line) = match token {
+                Token::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue;
+                }
+                Token::Output(source, line) => {
+                    let (expr, filters) = self.output(&source, line)?;
+                    nodes.push(Node::Output(expr, filters));
+                    continue;
+                }
+                Token::Tag(tag, line) => (tag, line),
+            };
+            let (keyword, args) = match tag.split_once(char::is_whitespace) {
+                Some((keyword, args)) => (keyword.to_string(), args.trim().to_string()),
+                None => (tag, String::new()),
+            };
+            if ends.contains(&keyword.as_str()) {
+                return Ok((nodes, (keyword, args, line)));
+            }
+            match keyword.as_str() {
+                "if" => nodes.push(self.if_tag(&args, line)?),
+                "for" => nodes.push(self.for_tag(&args, line)?),
+                "include" => nodes.push(Node::Include(self.string(&args, line)?)),
+                "extends" => {
+                    if self.extends.is_some() {
+                        return Err(self.error(line, "extends appears twice"));
+                    }
+                    self.extends = Some(self.string(&args, line)?);
+                }
+                "block" => {
+                    if !is_identifier(&args) {
+                        return Err(self.error(line, "block needs a name"));
+                    }
+                    let (body, _) = self.nodes(&["endblock"])?;
+                    if self.blocks.insert(args.clone(), Arc::new(body)).is_some() {
+                        return Err(self.error(line, &format!("block {} appears twice", args)));
+                    }
+                    nodes.push(Node::Block(args));
+                }
+                _ => return Err(self.error(line, &format!("unexpected {{% {} %}}", keyword))),
+            }
+        }
+        match ends.last() {
+            Some(end) => Err(self.error(0, &format!("missing {{% {} %}}", end))),
+            None => Ok((nodes, (String::new(), String::new(), 0))),
+        }
+    }
+
+    fn if_tag(&mut self, condition: &str, line: usize) -> Result<Node> {
+        let mut branches = Vec::new();
+        let mut condition = self.expr(condition, line)?;
+        loop {
+            let (body, (end, args, line)) = self.nodes(&["elif", "else", "endif"])?;
+            branches.push((condition, body));
+            match end.as_str() {
+                "elif" => condition = self.expr(&args, line)?,
+                "else" => {
+                    let (otherwise, _) = self.nodes(&["endif"])?;
+                    return Ok(Node::If(branches, otherwise));
+                }
+                _ => return Ok(Node::If(branches, Vec::new())),
+            }
+        }
+    }
+
+    // {% for value in iterable %} or {% for key, value in iterable %}, where keys are indexes
+    // of arrays or names of objects.
+    fn for_tag(&mut self, args: &str, line: usize) -> Result<Node> {
+        let (vars, iterable) = args
+            .split_once(" in ")
+            .ok_or_else(|| self.error(line, "expected {% for item in items %}"))?;
+        let vars: Vec<&str> = vars.split(',').map(str::trim).collect();
+        if !vars.iter().all(|var| is_identifier(var)) {
+            return Err(self.error(line, "invalid loop variable"));
+        }
+        let (key, value) = match vars.as_slice() {
+            [value] => (None, value.to_string()),
+            [key, value] => (Some(key.to_string()), value.to_string()),
+            _ => return Err(self.error(line, "too many loop variables")),
+        };
+        let iterable = self.expr(iterable, line)?;
+        let (body, (end, _, _)) = self.nodes(&["else", "endfor"])?;
+        let otherwise = if end == "else" {
+            self.nodes(&["endfor"])?.0
+        } else {
+            Vec::new()
+        };
+        Ok(Node::For {
+            key,
+            value,
+            iterable,
+            body,
+            otherwise,
+        })
+    }
+
+    fn output(&self, source: &str, line: usize) -> Result<(Expr, Vec<Filter>)> {
+        let (mut parser, expr) = self.parse_expr(source, line)?;
+        let mut filters = Vec::new();
+        while parser.eat("|") {
+            let filter = match parser.tokens.get(parser.pos).map(String::as_str) {
+                Some("raw") => Filter::Raw,
+                Some("upper") => Filter::Upper,
+                Some("lower") => Filter::Lower,
+                Some("length") => Filter::Length,
+                Some(filter) => return Err(self.error(line, &format!("unknown filter {}", filter))),
+                None => return Err(self.error(line, "missing filter after |")),
+            };
+            parser.pos += 1;
+            filters.push(filter);
+        }
+        self.end_expr(&parser, line)?;
+        Ok((expr, filters))
+    }
+
+    fn expr(&self, source: &str, line: usize) -> Result<Expr> {
+        let (parser, expr) = self.parse_expr(source, line)?;
+        self.end_expr(&parser, line)?;
+        Ok(expr)
+    }
+
+    fn parse_expr(&self, source: &str, line: usize) -> Result<(ExprParser, Expr)> {
+        let tokens = lex_expr(source).map_err(|e| self.error(line, &e))?;
+        let mut parser = ExprParser { tokens, pos: 0 };
+        let expr = parser.or().map_err(|e| self.error(line, &e))?;
+        Ok((parser, expr))
+    }
+
+    fn end_expr(&self, parser: &ExprParser, line: usize) -> Result<()> {
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(self.error(line, &format!("unexpected {} in expression", token))),
+            None => Ok(()),
+        }
+    }
+
+    fn string(&self, source: &str, line: usize) -> Result<String> {
+        match self.expr(source, line)? {
+            Expr::Literal(Value::String(s)) => Ok(s),
+            _ => Err(self.error(line, "expected a quoted template name")),
+        }
+    }
+
+    fn error(&self, line: usize, message: &str) -> Error {
+        syntax_error(self.name, line, message)
+    }
+}
+
+fn lex_expr(source: &str) -> std::result::Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            _ if ch.is_whitespace() => {}
+            '"' | '\'' => {
+                let end = source[start + 1..]
+                    .find(ch)
+                    .ok_or_else(|| "unterminated string".to_string())?;
+                tokens.push(source[start..start + end + 2].to_string());
+                while chars.peek().is_some_and(|&(i, _)| i <= start + end + 1) {
+                    chars.next();
+                }
+            }
+            '=' | '!' => match chars.next() {
+                Some((_, '=')) => tokens.push(format!("{}=", ch)),
+                _ => return Err(format!("unexpected {}", ch)),
+            },
+            '(' | ')' | '|' => tokens.push(ch.to_string()),
+            _ if ch.is_alphanumeric() || ch == '_' || ch == '-' => {
+                let mut end = start + ch.len_utf8();
+                while let Some(&(i, next)) = chars.peek() {
+                    if !(next.is_alphanumeric() || next == '_' || next == '.') {
+                        break;
+                    }
+                    end = i + next.len_utf8();
+                    chars.next();
+                }
+                tokens.push(source[start..end].to_string());
+            }
+            _ => return Err(format!("unexpected {}", ch)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn or(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> std::result::Result<Expr, String> {
+        if self.eat("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.primary()?;
+        if self.eat("==") {
+            Ok(Expr::Eq(Box::new(left), Box::new(self.primary()?)))
+        } else if self.eat("!=") {
+            Ok(Expr::Ne(Box::new(left), Box::new(self.primary()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn primary(&mut self) -> std::result::Result<Expr, String> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => return Err("expression ends early".to_string()),
+        };
+        self.pos += 1;
+        if token == "(" {
+            let expr = self.or()?;
+            return if self.eat(")") {
+                Ok(expr)
+            } else {
+                Err("missing )".to_string())
+            };
+        }
+        if token.starts_with(['"', '\'']) {
+            return Ok(Expr::Literal(Value::String(
+                token[1..token.len() - 1].to_string(),
+            )));
+        }
+        match token.as_str() {
+            "true" => return Ok(Expr::Literal(Value::Bool(true))),
+            "false" => return Ok(Expr::Literal(Value::Bool(false))),
+            "null" => return Ok(Expr::Literal(Value::Null)),
+            _ => {}
+        }
+        if token.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-') {
+            let number = match token.parse::<i64>() {
+                Ok(n) => Value::from(n),
+                Err(_) => token
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .ok_or_else(|| format!("invalid number {}", token))?,
+            };
+            return Ok(Expr::Literal(number));
+        }
+        let path: Vec<String> = token.split('.').map(str::to_string).collect();
+        if path.iter().any(|key| key.is_empty()) || !is_identifier(&path[0]) {
+            return Err(format!("unexpected {}", token));
+        }
+        Ok(Expr::Path(path))
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let matches = self.tokens.get(self.pos).is_some_and(|t| t == token);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+}
+
+struct Render<'a> {
+    templates: &'a Templates,
+    root: &'a Value,
+    // Loop variables, innermost last
+    scopes: Vec<Map<String, Value>>,
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+    depth: usize,
+    out: String,
+}
+
+impl Render<'_> {
+    fn template(&mut self, name: &str) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(nested_too_deep(name));
+        }
+        // The blocks of a template replace those of the templates it extends
+        let mut blocks = HashMap::new();
+        let mut template = self.templates.get(name)?;
+        let mut depth = self.depth;
+        while let Some(parent) = &template.extends {
+            for (name, nodes) in &template.blocks {
+                blocks.entry(name.clone()).or_insert_with(|| nodes.clone());
+            }
+            depth += 1;
+            if depth > MAX_DEPTH {
+                return Err(nested_too_deep(parent));
+            }
+            template = self.templates.get(parent)?;
+        }
+        for (name, nodes) in &template.blocks {
+            blocks.entry(name.clone()).or_insert_with(|| nodes.clone());
+        }
+
+        let blocks = std::mem::replace(&mut self.blocks, blocks);
+        let result = self.nodes(&template.nodes);
+        self.blocks = blocks;
+        self.depth -= 1;
+        result
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.out.push_str(text),
+                Node::Output(expr, filters) => {
+                    let mut value = self.eval(expr);
+                    let mut raw = false;
+                    for filter in filters {
+                        value = match filter {
+                            Filter::Raw => {
+                                raw = true;
+                                value
+                            }
+                            Filter::Upper => Value::String(display(&value).to_uppercase()),
+                            Filter::Lower => Value::String(display(&value).to_lowercase()),
+                            Filter::Length => Value::from(length(&value)),
+                        };
+                    }
+                    if raw {
+                        self.out.push_str(&display(&value));
+                    } else {
+                        self.out.push_str(&escape_html(&display(&value)));
+                    }
+                }
+                Node::If(branches, otherwise) => {
+                    let body = branches
+                        .iter()
+                        .find(|(condition, _)| is_truthy(&self.eval(condition)))
+                        .map_or(otherwise, |(_, body)| body);
+                    self.nodes(body)?;
+                }
+                Node::For {
+                    key,
+                    value,
+                    iterable,
+                    body,
+                    otherwise,
+                } => {
+                    let items: Vec<(Value, Value)> = match self.eval(iterable) {
+                        Value::Array(items) => items
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, item)| (Value::from(i), item))
+                            .collect(),
+                        Value::Object(items) => items
+                            .into_iter()
+                            .map(|(key, item)| (Value::String(key), item))
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    if items.is_empty() {
+                        self.nodes(otherwise)?;
+                    }
+                    let len = items.len();
+                    for (i, (item_key, item)) in items.into_iter().enumerate() {
+                        let mut scope = Map::new();
+                        if let Some(key) = key {
+                            scope.insert(key.clone(), item_key);
+                        }
+                        scope.insert(value.clone(), item);
+                        scope.insert(
+                            "loop".to_string(),
+                            serde_json::json!({
+                                "index": i + 1,
+                                "index0": i,
+                                "first": i == 0,
+                                "last": i + 1 == len,
+                                "length": len,
+                            }),
+                        );
+                        self.scopes.push(scope);
+                        let result = self.nodes(body);
+                        self.scopes.pop();
+                        result?;
+                    }
+                }
+                Node::Include(name) => self.template(name)?,
+                Node::Block(name) => {
+                    if let Some(body) = self.blocks.get(name).cloned() {
+                        self.nodes(&body)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Path(path) => self.lookup(path),
+            Expr::Literal(value) => value.clone(),
+            Expr::Not(expr) => Value::Bool(!is_truthy(&self.eval(expr))),
+            Expr::And(left, right) => {
+                Value::Bool(is_truthy(&self.eval(left)) && is_truthy(&self.eval(right)))
+            }
+            Expr::Or(left, right) => {
+                Value::Bool(is_truthy(&self.eval(left)) || is_truthy(&self.eval(right)))
+            }
+            Expr::Eq(left, right) => Value::Bool(is_equal(&self.eval(left), &self.eval(right))),
+            Expr::Ne(left, right) => Value::Bool(!is_equal(&self.eval(left), &self.eval(right))),
+        }
+    }
+
+    fn lookup(&self, path: &[String]) -> Value {
+        let mut value = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&path[0]))
+            .or_else(|| self.root.get(&path[0]));
+        for key in &path[1..] {
+            value = value.and_then(|value| match value {
+                Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+                Value::Object(fields) => fields.get(key),
+                _ => None,
+            });
+        }
+        value.cloned().unwrap_or(Value::Null)
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+// Numbers are equal whether integers or not.
+fn is_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn length(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.len(),
+        Value::Object(fields) => fields.len(),
+        _ => 0,
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+        && s.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+fn syntax_error(name: &str, line: usize, message: &str) -> Error {
+    let message = if line > 0 {
+        format!("{}:{}: {}", name, line, message)
+    } else {
+        format!("{}: {}", name, message)
+    };
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn nested_too_deep(name: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{}: templates nested more than {} deep", name, MAX_DEPTH),
+    )
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,222 @@
+use std::io::{BufRead, Cursor, Error, ErrorKind, Read, Result};
+
+use crate::http::{
+    ChunkedReader, Connection, Headers, SharedBuffer, METHOD_DELETE, METHOD_GET, METHOD_HEAD,
+    METHOD_PATCH, METHOD_POST, METHOD_PUT,
+};
+use crate::server::send_error;
+use crate::Router;
+
+// Runs requests through a router in memory, with the same parsing, middleware, routing and
+// response encoding as a served connection, but without a socket:
+//
+//     let client = TestClient::new(router);
+//     let response = client.get("/users/1").with_header("Accept", "application/json").send()?;
+//     assert_eq!(response.status(), 200);
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> Self {
+        TestClient { router }
+    }
+
+    pub fn request(&self, method: &str, target: &str) -> TestRequest<'_> {
+        TestRequest {
+            client: self,
+            method: method.to_string(),
+            target: target.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_GET, target)
+    }
+
+    pub fn head(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_HEAD, target)
+    }
+
+    pub fn post(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_POST, target)
+    }
+
+    pub fn put(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_PUT, target)
+    }
+
+    pub fn patch(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_PATCH, target)
+    }
+
+    pub fn delete(&self, target: &str) -> TestRequest<'_> {
+        self.request(METHOD_DELETE, target)
+    }
+
+    // Sends raw bytes, which need not be a well-formed request, and parses the first response.
+    pub fn send_raw(&self, request: &[u8]) -> Result<TestResponse> {
+        let output = SharedBuffer::default();
+        let mut conn = Connection::from_io(Cursor::new(request.to_vec()), output.clone());
+        conn.set_limits(self.router.limits);
+        match conn.next_request() {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "no request to dispatch",
+                ))
+            }
+            Err(e) => {
+                send_error(&mut conn, e)?;
+                return TestResponse::parse(&output.take(), false);
+            }
+        }
+
+        match self.router.dispatch(&mut conn) {
+            Ok(response) => conn.send(response)?,
+            Err(e) => send_error(&mut conn, e)?,
+        }
+        let is_head = conn.method == METHOD_HEAD;
+        drop(conn);
+        TestResponse::parse(&output.take(), is_head)
+    }
+}
+
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    method: String,
+    target: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl<'a> TestRequest<'a> {
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.append(key, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    // Sends the request with Host and Content-Length filled in unless they were given.
+    pub fn send(self) -> Result<TestResponse> {
+        let mut request = format!("{} {} HTTP/1.1\r\n", self.method, self.target).into_bytes();
+        if !self.headers.contains("Host") {
+            request.extend_from_slice(b"Host: localhost\r\n");
+        }
+        if !self.body.is_empty()
+            && !self.headers.contains("Content-Length")
+            && !self.headers.contains("Transfer-Encoding")
+        {
+            request
+                .extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        for (key, value) in self.headers.iter() {
+            request.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+        request.extend_from_slice(b"\r\n");
+        request.extend_from_slice(&self.body);
+        self.client.send_raw(&request)
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    version: String,
+    status: u16,
+    reason: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key)
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    // The body as sent, after undoing chunked transfer coding.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    fn parse(bytes: &[u8], is_head: bool) -> Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut reader = bytes;
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let version = parts.next().unwrap_or("").to_string();
+        let status = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid("invalid status line"))?;
+        let reason = parts.next().unwrap_or("").to_string();
+
+        let mut headers = Headers::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("response ended in the headers"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("invalid header"))?;
+            headers.append(key, value.trim());
+        }
+
+        let mut body = Vec::new();
+        let has_body = !(is_head || status / 100 == 1 || status == 204 || status == 304);
+        if has_body {
+            if headers.has_token("Transfer-Encoding", "chunked") {
+                ChunkedReader::new(reader).read_to_end(&mut body)?;
+            } else if let Some(len) = headers.get("Content-Length") {
+                let len: usize = len.parse().map_err(|_| invalid("invalid Content-Length"))?;
+                body = reader
+                    .get(..len)
+                    .ok_or_else(|| invalid("body too short"))?
+                    .to_vec();
+            } else {
+                body = reader.to_vec();
+            }
+        }
+
+        Ok(TestResponse {
+            version,
+            status,
+            reason,
+            headers,
+            body,
+        })
+    }
+}
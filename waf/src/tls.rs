@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection};
+
+// A certificate chain with its private key.
+#[derive(Clone)]
+pub struct Certificate(Arc<CertifiedKey>);
+
+impl Certificate {
+    // cert holds the chain, leaf first. key is a PKCS#8, PKCS#1 or SEC1 private key.
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<Self> {
+        let chain = CertificateDer::pem_slice_iter(cert)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(pem_error)?;
+        if chain.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "no certificate in PEM"));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(pem_error)?;
+        let key = CertifiedKey::from_der(chain, key, &provider())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(Certificate(Arc::new(key)))
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(cert: P, key: Q) -> Result<Self> {
+        Certificate::from_pem(&std::fs::read(cert)?, &std::fs::read(key)?)
+    }
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Certificate").finish_non_exhaustive()
+    }
+}
+
+// The certificates a TLS listener presents. Clients naming a server through SNI get the
+// certificate added for that name, all others the default one:
+//
+//     let tls = TlsConfig::new(Certificate::from_pem_files("cert.pem", "key.pem")?)
+//         .with_server_name("*.example.com", Certificate::from_pem_files("wild.pem", "wild.key")?);
+//     router.set_tls(tls);
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    default: Certificate,
+    names: HashMap<String, Certificate>,
+}
+
+impl TlsConfig {
+    pub fn new(default: Certificate) -> Self {
+        TlsConfig {
+            default,
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(cert: P, key: Q) -> Result<Self> {
+        Ok(TlsConfig::new(Certificate::from_pem_files(cert, key)?))
+    }
+
+    // name may start with "*." to match any single label in its place.
+    pub fn with_server_name(mut self, name: &str, certificate: Certificate) -> Self {
+        self.names.insert(name.to_ascii_lowercase(), certificate);
+        self
+    }
+
+    pub(crate) fn server_config(self) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    fn find(&self, name: &str) -> Option<&Certificate> {
+        let name = name.to_ascii_lowercase();
+        if let Some(certificate) = self.names.get(&name) {
+            return Some(certificate);
+        }
+        let (_, parent) = name.split_once('.')?;
+        self.names.get(&format!("*.{}", parent))
+    }
+}
+
+impl ResolvesServerCert for TlsConfig {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificate = client_hello
+            .server_name()
+            .and_then(|name| self.find(name))
+            .unwrap_or(&self.default);
+        Some(certificate.0.clone())
+    }
+}
+
+// A server side TLS session, split into a reading and a writing half so that a read waiting
+// for the client doesn't hold writes up. The handshake happens on the first read.
+pub(crate) fn split(
+    config: Arc<ServerConfig>,
+    socket: TcpStream,
+) -> Result<(TlsReader, TlsWriter)> {
+    let conn = ServerConnection::new(config).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let session = Arc::new(Session {
+        conn: Mutex::new(conn),
+        socket: Mutex::new(socket.try_clone()?),
+    });
+    let reader = TlsReader {
+        session: session.clone(),
+        socket,
+        records: vec![0; 16 * 1024].into_boxed_slice(),
+    };
+    Ok((reader, TlsWriter(session)))
+}
+
+struct Session {
+    conn: Mutex<ServerConnection>,
+    // The socket for writing. Locked before conn, and held while records are written so that
+    // they go out in order without blocking the reader.
+    socket: Mutex<TcpStream>,
+}
+
+impl Session {
+    fn conn(&self) -> MutexGuard<'_, ServerConnection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Sends the TLS records the session has queued.
+    fn send_records(&self) -> Result<()> {
+        let mut socket = self.socket.lock().unwrap_or_else(|e| e.into_inner());
+        let mut records = Vec::new();
+        {
+            let mut conn = self.conn();
+            while conn.wants_write() {
+                conn.write_tls(&mut records)?;
+            }
+        }
+        socket.write_all(&records)
+    }
+}
+
+// Tells the client the response is complete rather than truncated.
+impl Drop for Session {
+    fn drop(&mut self) {
+        let conn = self.conn.get_mut().unwrap_or_else(|e| e.into_inner());
+        if !conn.is_handshaking() {
+            conn.send_close_notify();
+            let _ = self.send_records();
+        }
+    }
+}
+
+pub(crate) struct TlsReader {
+    session: Arc<Session>,
+    socket: TcpStream,
+    records: Box<[u8]>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.session.conn().reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+
+            // Read from the socket without holding the session, which writes need
+            let n = self.socket.read(&mut self.records)?;
+            let processed = {
+                let mut conn = self.session.conn();
+                let mut records = &self.records[..n];
+                // An empty read tells the session about EOF
+                conn.read_tls(&mut records)?;
+                while !records.is_empty() {
+                    conn.read_tls(&mut records)?;
+                }
+                conn.process_new_packets().map(|_| ())
+            };
+            // Handshake messages, or the alert for a failed one
+            self.session.send_records()?;
+            processed.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        }
+    }
+}
+
+pub(crate) struct TlsWriter(Arc<Session>);
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.0.conn().writer().write(buf)?;
+        self.0.send_records()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.conn().writer().flush()?;
+        self.0.send_records()
+    }
+}
+
+fn provider() -> CryptoProvider {
+    ring::default_provider()
+}
+
+fn pem_error(e: rustls::pki_types::pem::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
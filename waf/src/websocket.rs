@@ -0,0 +1,478 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::http::{Connection, METHOD_GET};
+use crate::response::Response;
+use crate::Handler;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED: u16 = 1003;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+// Performs the opening handshake of RFC 6455 and runs session on the upgraded connection. Only
+// Router::listen supports it; the async server doesn't keep connections open for handlers.
+//
+//     router.add_handler(METHOD_GET, "/echo", WebSocketHandler::new(|ws: &mut WebSocket| {
+//         while let Some(message) = ws.recv()? {
+//             ws.send(&message)?;
+//         }
+//         Ok(())
+//     }));
+pub struct WebSocketHandler<F> {
+    session: F,
+    protocols: Vec<String>,
+    max_message_size: usize,
+}
+
+impl<F> WebSocketHandler<F>
+where
+    F: Fn(&mut WebSocket) -> Result<()> + Send + Sync + 'static,
+{
+    pub fn new(session: F) -> Self {
+        WebSocketHandler {
+            session,
+            protocols: Vec::new(),
+            max_message_size: 16 * 1024 * 1024,
+        }
+    }
+
+    // Subprotocols the server speaks, by preference. The first one the client offers is chosen.
+    pub fn with_protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    // Larger frames and messages are refused with close code 1009.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    fn select_protocol(&self, conn: &Connection) -> Option<String> {
+        let offered: Vec<&str> = conn
+            .headers
+            .get_all("Sec-WebSocket-Protocol")
+            .flat_map(|value| value.split(','))
+            .map(|p| p.trim())
+            .collect();
+        self.protocols
+            .iter()
+            .find(|p| offered.contains(&p.as_str()))
+            .cloned()
+    }
+}
+
+impl<F> Handler for WebSocketHandler<F>
+where
+    F: Fn(&mut WebSocket) -> Result<()> + Send + Sync + 'static,
+{
+    fn handle(&self, conn: &mut Connection) -> Result<Response> {
+        let key = match check_handshake(conn) {
+            Ok(key) => key,
+            Err(response) => return Ok(response),
+        };
+        let protocol = self.select_protocol(conn);
+
+        let mut response = Response::new(101)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", &accept_key(&key));
+        if let Some(protocol) = &protocol {
+            response.set_header("Sec-WebSocket-Protocol", protocol);
+        }
+        conn.upgrade(response)?;
+
+        let mut ws = WebSocket {
+            conn,
+            protocol,
+            max_message_size: self.max_message_size,
+            fragments: None,
+            partial: Vec::new(),
+            close_sent: false,
+            closed: false,
+        };
+        let result = (self.session)(&mut ws);
+        if !ws.close_sent {
+            let code = match result {
+                Ok(()) => CLOSE_NORMAL,
+                Err(_) => CLOSE_INTERNAL_ERROR,
+            };
+            let _ = ws.close(code, "");
+        }
+        result.map(|_| Response::new(101))
+    }
+}
+
+// Returns the Sec-WebSocket-Key of a valid opening handshake, or the response refusing it.
+fn check_handshake(conn: &Connection) -> std::result::Result<String, Response> {
+    let upgrade_required = || {
+        Response::new(426)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+    };
+    if !conn.headers.has_token("Upgrade", "websocket")
+        || !conn.headers.has_token("Connection", "Upgrade")
+    {
+        return Err(upgrade_required());
+    }
+    if conn.method != METHOD_GET || conn.version != "HTTP/1.1" {
+        return Err(Response::new(400));
+    }
+    if conn.header("Sec-WebSocket-Version").map(|v| v.trim()) != Some("13") {
+        return Err(upgrade_required().with_header("Sec-WebSocket-Version", "13"));
+    }
+    let key = conn.header("Sec-WebSocket-Key").unwrap_or("").trim();
+    match BASE64.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => Ok(key.to_string()),
+        _ => Err(Response::new(400)),
+    }
+}
+
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+// A single frame, with its payload unmasked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    // The status code and reason the peer closed with, if it gave one
+    Close(Option<(u16, String)>),
+}
+
+pub struct WebSocket<'a> {
+    conn: &'a mut Connection,
+    protocol: Option<String>,
+    max_message_size: usize,
+    fragments: Option<(Opcode, Vec<u8>)>,
+    // The bytes of a frame whose read timed out, which the next read continues from
+    partial: Vec<u8>,
+    close_sent: bool,
+    closed: bool,
+}
+
+impl<'a> WebSocket<'a> {
+    // The underlying connection, e.g. for the request's path, headers and params.
+    pub fn connection(&self) -> &Connection {
+        self.conn
+    }
+
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    // recv() waits for the peer as long as it takes by default. A timeout makes it fail with
+    // ErrorKind::WouldBlock or TimedOut instead, so that a handler can push messages between
+    // reads.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.conn.set_read_timeout(timeout)
+    }
+
+    // Receives the next message, joining fragmented ones. Pings are answered and a close is
+    // echoed before they are returned. None once the connection is closed.
+    pub fn recv(&mut self) -> Result<Option<Message>> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            let frame = match self.read_frame() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    self.closed = true;
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.write_frame(&Frame::new(Opcode::Pong, frame.payload.clone()))?;
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+                Opcode::Close => return self.receive_close(frame.payload).map(Some),
+                Opcode::Continuation => {
+                    let (opcode, mut data) = match self.fragments.take() {
+                        Some(fragments) => fragments,
+                        None => {
+                            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unexpected continuation"))
+                        }
+                    };
+                    if data.len() + frame.payload.len() > self.max_message_size {
+                        return Err(self.fail(CLOSE_TOO_BIG, "message too big"));
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.message(opcode, data).map(Some);
+                    }
+                    self.fragments = Some((opcode, data));
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(self.fail(CLOSE_PROTOCOL_ERROR, "expected continuation"));
+                    }
+                    if frame.fin {
+                        return self.message(frame.opcode, frame.payload).map(Some);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<()> {
+        match message {
+            Message::Text(text) => self.send_text(text),
+            Message::Binary(data) => self.send_binary(data),
+            Message::Ping(data) => self.write_frame(&Frame::new(Opcode::Ping, data.clone())),
+            Message::Pong(data) => self.write_frame(&Frame::new(Opcode::Pong, data.clone())),
+            Message::Close(Some((code, reason))) => self.close(*code, reason),
+            Message::Close(None) => self.close(CLOSE_NORMAL, ""),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<()> {
+        self.write_frame(&Frame::new(Opcode::Text, text.as_bytes().to_vec()))
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<()> {
+        self.write_frame(&Frame::new(Opcode::Binary, data.to_vec()))
+    }
+
+    // Starts the closing handshake. recv() returns the peer's close once it answers.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        // Control frames carry at most 125 bytes, two of them for the code
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write_frame(&Frame::new(Opcode::Close, payload))?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    // Reads one frame as it is, without answering pings or joining fragments. Frames violating
+    // the protocol make the connection fail with the matching close code. After a read timeout,
+    // the next call continues the frame where it stopped.
+    pub fn read_frame(&mut self) -> Result<Frame> {
+        self.fill(2)?;
+        let head = [self.partial[0], self.partial[1]];
+        let fin = head[0] & 0x80 != 0;
+        let masked = head[1] & 0x80 != 0;
+
+        // No extensions are negotiated, so the reserved bits must be clear
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "reserved bits set"));
+        }
+        let opcode = match Opcode::from_u8(head[0] & 0x0f) {
+            Some(opcode) => opcode,
+            None => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unknown opcode")),
+        };
+        // Clients must mask every frame (RFC 6455 5.1)
+        if !masked {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unmasked client frame"));
+        }
+
+        let (len, mask_start) = match head[1] & 0x7f {
+            126 => {
+                self.fill(4)?;
+                let mut len = [0; 2];
+                len.copy_from_slice(&self.partial[2..4]);
+                (u16::from_be_bytes(len) as u64, 4)
+            }
+            127 => {
+                self.fill(10)?;
+                let mut len = [0; 8];
+                len.copy_from_slice(&self.partial[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (len > 125 || !fin) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid control frame"));
+        }
+        if len > self.max_message_size as u64 {
+            return Err(self.fail(CLOSE_TOO_BIG, "frame too big"));
+        }
+
+        let payload_start = mask_start + 4;
+        self.fill(payload_start + len as usize)?;
+        let mut payload = self.partial.split_off(payload_start);
+        let mask = &self.partial[mask_start..];
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        self.partial.clear();
+
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    // Reads until the partial frame holds len bytes. Bytes read before an error are kept.
+    fn fill(&mut self, len: usize) -> Result<()> {
+        let mut filled = self.partial.len();
+        if filled >= len {
+            return Ok(());
+        }
+        self.partial.resize(len, 0);
+        while filled < len {
+            match self.conn.read(&mut self.partial[filled..]) {
+                Ok(0) => {
+                    self.partial.truncate(filled);
+                    return Err(Error::from(ErrorKind::UnexpectedEof));
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.partial.truncate(filled);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes one frame as it is. Frames from the server are never masked.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        let mut head = vec![(frame.fin as u8) << 7 | frame.opcode.as_u8()];
+        let len = frame.payload.len();
+        if len < 126 {
+            head.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            head.push(126);
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            head.push(127);
+            head.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        self.conn.write_all(&head)?;
+        self.conn.write_all(&frame.payload)?;
+        self.conn.flush()
+    }
+
+    fn message(&mut self, opcode: Opcode, data: Vec<u8>) -> Result<Message> {
+        match opcode {
+            Opcode::Text => match String::from_utf8(data) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "text is not UTF-8")),
+            },
+            _ => Ok(Message::Binary(data)),
+        }
+    }
+
+    fn receive_close(&mut self, payload: Vec<u8>) -> Result<Message> {
+        let status = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid close payload")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !is_valid_close_code(code) {
+                    return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid close code"));
+                }
+                match String::from_utf8(payload[2..].to_vec()) {
+                    Ok(reason) => Some((code, reason)),
+                    Err(_) => return Err(self.fail(CLOSE_INVALID_DATA, "reason is not UTF-8")),
+                }
+            }
+        };
+        if !self.close_sent {
+            let code = status.as_ref().map_or(CLOSE_NORMAL, |(code, _)| *code);
+            self.close(code, "")?;
+        }
+        self.closed = true;
+        Ok(Message::Close(status))
+    }
+
+    // Closes the connection with code after a protocol violation and returns the error to fail
+    // the session with.
+    fn fail(&mut self, code: u16, message: &str) -> Error {
+        let _ = self.close(code, message);
+        self.closed = true;
+        Error::new(ErrorKind::InvalidData, message)
+    }
+}
+
+// Codes an endpoint may send in a close frame (RFC 6455 7.4)
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
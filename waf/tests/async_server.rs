@@ -0,0 +1,312 @@
+#![cfg(feature = "async")]
+extern crate waf;
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener as StdListener, TcpStream};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+
+use waf::http::{Connection, Request, METHOD_GET, METHOD_POST};
+use waf::middleware::Next;
+use waf::response::Response;
+use waf::Router;
+
+struct AsyncServer {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl AsyncServer {
+    fn start(router: Router) -> Self {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let thread = thread::spawn(move || {
+            runtime.block_on(router.serve_async(listener, async {
+                let _ = stopped.await;
+            }))
+        });
+        AsyncServer { addr, stop, thread }
+    }
+
+    fn shutdown(self) -> io::Result<()> {
+        let _ = self.stop.send(());
+        self.thread.join().unwrap()
+    }
+}
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router.add_async_handler(METHOD_GET, "/", |_req: Request| async {
+        Ok(Response::text("Hello"))
+    });
+    router.add_async_handler(METHOD_POST, "/echo", |req: Request| async move {
+        Ok(Response::text(
+            String::from_utf8_lossy(&req.body).into_owned(),
+        ))
+    });
+    router.add_async_handler(METHOD_GET, "/panic", |_req: Request| async {
+        if true {
+            panic!("async handler panicked");
+        }
+        Ok(Response::ok())
+    });
+    router.add_handler(METHOD_GET, "/sleep/:ms<uint>", |conn: &mut Connection| {
+        thread::sleep(Duration::from_millis(conn.params.parse("ms")?));
+        Ok(Response::text("Good morning"))
+    });
+    router
+}
+
+fn request(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    request(
+        addr,
+        &format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ),
+    )
+}
+
+#[test]
+fn test_async_handler() {
+    let server = AsyncServer::start(router());
+    let response = get(server.addr, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+
+    let response = request(
+        server.addr,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nabcde"));
+
+    let response = get(server.addr, "/sleep/10");
+    assert!(response.ends_with("\r\n\r\nGood morning"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_async_handler_behind_middleware() {
+    let mut router = router();
+    router.add_middleware(|conn: &mut Connection, next: Next| {
+        let mut response = next.run(conn)?;
+        response.set_header("X-Middleware", "yes");
+        Ok(response)
+    });
+
+    // Run on the blocking pool through the middleware
+    let server = AsyncServer::start(router);
+    let response = get(server.addr, "/");
+    assert!(response.contains("\r\nX-Middleware: yes\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_async_handler_on_threaded_server() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let response = get(server.local_addr(), "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_panicking_async_handler() {
+    let mut router = router();
+    router.set_error_log(|_: &str| {});
+    let server = AsyncServer::start(router);
+    let response = get(server.addr, "/panic");
+    assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+    // The server keeps serving
+    assert!(get(server.addr, "/").ends_with("Hello"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_keep_alive_and_pipelining() {
+    let server = AsyncServer::start(router());
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi",
+        )
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+    assert!(response.contains("\r\n\r\nHello"));
+    assert!(response.ends_with("\r\n\r\nhi"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_responses_are_streamed() {
+    let (release, released) = mpsc::channel::<()>();
+    let released = std::sync::Mutex::new(Some(released));
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/", move |_conn: &mut Connection| {
+        let released = released.lock().unwrap().take().unwrap();
+        Ok(Response::streaming(move |writer| {
+            writer.write_all(b"first\n")?;
+            writer.flush()?;
+            let _ = released.recv();
+            writer.write_all(b"second\n")
+        }))
+    });
+    let server = AsyncServer::start(router);
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while !line.contains("first") {
+        line.clear();
+        assert!(reader.read_line(&mut line).unwrap() > 0);
+    }
+
+    // The first part arrived while the handler is still writing the body
+    release.send(()).unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert!(rest.contains("second"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_slow_request_head_times_out() {
+    let mut router = router();
+    router.set_header_timeout(Some(Duration::from_millis(200)));
+    let server = AsyncServer::start(router);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+    let started = Instant::now();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_max_connections() {
+    let mut router = router();
+    router.set_max_connections(1);
+    let server = AsyncServer::start(router);
+
+    let mut first = TcpStream::connect(server.addr).unwrap();
+    first
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(first.read(&mut buf).unwrap() > 0);
+
+    let mut second = TcpStream::connect(server.addr).unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    assert!(get(server.addr, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_shutdown_drains_in_flight_requests() {
+    let server = AsyncServer::start(router());
+    let addr = server.addr;
+
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(idle.read(&mut buf).unwrap() > 0);
+
+    let client = thread::spawn(move || get(addr, "/sleep/300"));
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let response = client.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("Good morning"));
+    assert_eq!(idle.read(&mut buf).unwrap_or(0), 0);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_shutdown_timeout() {
+    let mut router = router();
+    router.set_shutdown_timeout(Duration::from_millis(100));
+    let server = AsyncServer::start(router);
+    let addr = server.addr;
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /sleep/1000 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map(|_| response)
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let e = server.shutdown().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(client.join().unwrap().map_or(true, |r| r.is_empty()));
+}
+
+#[test]
+fn test_listen_async() {
+    // A port that was free a moment ago
+    let addr = StdListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || {
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(router().listen_async(&addr.to_string()))
+    });
+
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(5) => {
+                thread::sleep(Duration::from_millis(10))
+            }
+            Err(e) => panic!("{}", e),
+        }
+    };
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nHello"));
+}
@@ -0,0 +1,294 @@
+extern crate waf;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use waf::auth::{constant_time_eq, BasicAuth, BearerAuth, Guard, Identity, Jwt, JwtAuth, JwtKey};
+use waf::extract::handler;
+use waf::http::{Connection, METHOD_DELETE, METHOD_GET};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+const PRIVATE_KEY: &str = include_str!("data/jwt_rsa_private.pem");
+const PUBLIC_KEY: &str = include_str!("data/jwt_rsa_public.pem");
+const PUBLIC_KEY_PKCS1: &[u8] = include_bytes!("data/jwt_rsa_public.der");
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn whoami(identity: Identity) -> std::io::Result<Response> {
+    Ok(Response::text(identity.subject()))
+}
+
+#[test]
+fn test_basic_auth() {
+    let mut router = Router::new();
+    router.add_middleware(BasicAuth::new(
+        "admin area",
+        |user: &str, password: &str| {
+            if user == "alice" && constant_time_eq(password.as_bytes(), b"open: sesame") {
+                Some(Identity::new(user))
+            } else {
+                None
+            }
+        },
+    ));
+    router.add_handler(METHOD_GET, "/", handler(whoami));
+    let client = TestClient::new(router);
+
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(
+        response.header("WWW-Authenticate"),
+        Some("Basic realm=\"admin area\", charset=\"UTF-8\"")
+    );
+
+    let credentials = format!("Basic {}", STANDARD.encode("alice:open: sesame"));
+    let response = client
+        .get("/")
+        .with_header("Authorization", &credentials)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "alice");
+
+    let credentials = format!("Basic {}", STANDARD.encode("alice:wrong"));
+    let response = client
+        .get("/")
+        .with_header("Authorization", &credentials)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[test]
+fn test_bearer_and_guards() {
+    let mut router = Router::new();
+    router.add_middleware(
+        BearerAuth::new("api", |token: &str| match token {
+            "admin-key" => Some(Identity::new("root").with_roles(&["admin"])),
+            "user-key" => Some(Identity::new("bob")),
+            _ => None,
+        })
+        .optional(),
+    );
+    router.add_handler(METHOD_GET, "/public", |conn: &mut Connection| {
+        let name = conn
+            .identity()
+            .map_or("anonymous", |identity| identity.subject());
+        Ok(Response::text(name.to_string()))
+    });
+    router
+        .add_handler(METHOD_GET, "/me", handler(whoami))
+        .add_middleware(Guard::authenticated());
+    router
+        .add_handler(METHOD_DELETE, "/users/:id", |_conn: &mut Connection| {
+            Ok(Response::new(204))
+        })
+        .add_middleware(Guard::role("admin"));
+    let client = TestClient::new(router);
+
+    assert_eq!(client.get("/public").send().unwrap().text(), "anonymous");
+    let response = client
+        .get("/public")
+        .with_header("Authorization", "Bearer user-key")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "bob");
+
+    let response = client.get("/me").send().unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(
+        response.header("WWW-Authenticate"),
+        Some("Bearer realm=\"api\"")
+    );
+
+    let response = client
+        .get("/me")
+        .with_header("Authorization", "Bearer bad-key")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(response
+        .header("WWW-Authenticate")
+        .unwrap()
+        .contains("error=\"invalid_token\""));
+
+    let response = client
+        .delete("/users/1")
+        .with_header("Authorization", "Bearer user-key")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    let response = client
+        .delete("/users/1")
+        .with_header("Authorization", "bearer admin-key")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 204);
+}
+
+#[test]
+fn test_jwt_hs256() {
+    // The example token from jwt.io
+    let token = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.\
+                 eyJzdWIiOiIxMjM0NTY3ODkwIiwibmFtZSI6IkpvaG4gRG9lIiwiaWF0IjoxNTE2MjM5MDIyfQ.\
+                 SflKxwRJSMeKKF2QT4fwpMeJf36POk6yJV_adQssw5c";
+    // It has no expiry, so it only passes when exp isn't required
+    let jwt = Jwt::new(JwtKey::hs256(b"your-256-bit-secret"));
+    assert!(jwt.verify(token).is_err());
+    let jwt = jwt.with_required_claims(&["sub"]);
+    let claims = jwt.verify(token).unwrap();
+    assert_eq!(claims.subject(), Some("1234567890"));
+    assert_eq!(claims.get("name"), Some(&json!("John Doe")));
+
+    #[derive(Deserialize)]
+    struct Profile {
+        name: String,
+        iat: u64,
+    }
+    let profile: Profile = claims.deserialize().unwrap();
+    assert_eq!(profile.name, "John Doe");
+    assert_eq!(profile.iat, 1516239022);
+
+    assert!(Jwt::new(JwtKey::hs256(b"another secret"))
+        .with_required_claims(&[])
+        .verify(token)
+        .is_err());
+    let tampered = token.replace("eyJzdWIiOiIxMjM0NTY3ODkwIi", "eyJzdWIiOiIxMjM0NTY3ODkxIi");
+    assert!(jwt.verify(&tampered).is_err());
+    assert!(jwt.verify("not.a.token").is_err());
+
+    let jwt = Jwt::new(JwtKey::hs256(b"your-256-bit-secret"))
+        .with_issuer("auth")
+        .with_audience("api");
+    let valid = jwt
+        .sign(&json!({"sub": "a", "iss": "auth", "aud": ["web", "api"], "exp": now() + 60}))
+        .unwrap();
+    assert!(jwt.verify(&valid).is_ok());
+    for claims in &[
+        json!({"sub": "a", "iss": "auth", "aud": "api", "exp": now() - 120}),
+        json!({"sub": "a", "iss": "auth", "aud": "api", "exp": now() + 60, "nbf": now() + 120}),
+        json!({"sub": "a", "iss": "other", "aud": "api", "exp": now() + 60}),
+        json!({"sub": "a", "iss": "auth", "aud": "web", "exp": now() + 60}),
+        json!({"sub": "a", "iss": "auth", "exp": now() + 60}),
+        json!({"sub": "a", "iss": "auth", "aud": "api"}),
+    ] {
+        assert!(
+            jwt.verify(&jwt.sign(claims).unwrap()).is_err(),
+            "{}",
+            claims
+        );
+    }
+}
+
+#[test]
+fn test_jwt_rs256() {
+    let signer = Jwt::new(JwtKey::rs256_private_pem(PRIVATE_KEY).unwrap());
+    let verifier = Jwt::new(JwtKey::rs256_public_pem(PUBLIC_KEY).unwrap());
+    let token = signer
+        .sign(&json!({"sub": "carol", "scope": "read write", "exp": now() + 60}))
+        .unwrap();
+    let claims = verifier.verify(&token).unwrap();
+    assert_eq!(claims.subject(), Some("carol"));
+    assert_eq!(claims.roles(), vec!["read", "write"]);
+    assert!(verifier.sign(&json!({})).is_err());
+
+    // A token signed with HMAC, using the public key as the secret, must not pass.
+    let forged = Jwt::new(JwtKey::hs256(PUBLIC_KEY.as_bytes()))
+        .sign(&json!({"sub": "mallory", "exp": now() + 60}))
+        .unwrap();
+    assert!(verifier.verify(&forged).is_err());
+    assert!(JwtKey::rs256_public_pem(PRIVATE_KEY).is_err());
+    assert!(
+        JwtKey::rs256_public_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----")
+            .is_err()
+    );
+
+    let verifier = Jwt::new(JwtKey::rs256_public_der(PUBLIC_KEY_PKCS1).unwrap());
+    assert_eq!(verifier.verify(&token).unwrap().subject(), Some("carol"));
+    assert!(JwtKey::rs256_public_der(&PUBLIC_KEY_PKCS1[1..]).is_err());
+}
+
+#[test]
+fn test_jwt_auth() {
+    let secret = b"0123456789abcdef0123456789abcdef";
+    let mut router = Router::new();
+    router.add_middleware(JwtAuth::new("api", Jwt::new(JwtKey::hs256(secret))));
+    router
+        .add_handler(METHOD_GET, "/admin", handler(whoami))
+        .add_middleware(Guard::any_role(&["admin", "ops"]));
+    let client = TestClient::new(router);
+
+    let jwt = Jwt::new(JwtKey::hs256(secret));
+    let token = jwt
+        .sign(&json!({"sub": "dave", "roles": ["ops"], "exp": now() + 60}))
+        .unwrap();
+    let response = client
+        .get("/admin")
+        .with_header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "dave");
+
+    let token = jwt
+        .sign(&json!({"sub": "eve", "roles": ["user"], "exp": now() + 60}))
+        .unwrap();
+    let response = client
+        .get("/admin")
+        .with_header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let token = jwt
+        .sign(&json!({"sub": "eve", "exp": now() - 3600}))
+        .unwrap();
+    let response = client
+        .get("/admin")
+        .with_header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(response
+        .header("WWW-Authenticate")
+        .unwrap()
+        .contains("token expired"));
+
+    // Tokens without a subject don't make an identity
+    let token = jwt
+        .sign(&json!({"roles": ["admin"], "exp": now() + 60}))
+        .unwrap();
+    let response = client
+        .get("/admin")
+        .with_header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(response
+        .header("WWW-Authenticate")
+        .unwrap()
+        .contains("missing sub claim"));
+}
+
+#[test]
+fn test_guard_without_authentication() {
+    let mut router = Router::new();
+    router
+        .add_handler(METHOD_GET, "/admin", handler(whoami))
+        .add_middleware(Guard::authenticated());
+    let client = TestClient::new(router);
+
+    let response = client.get("/admin").send().unwrap();
+    assert_eq!(response.status(), 403);
+    assert_eq!(response.header("WWW-Authenticate"), None);
+}
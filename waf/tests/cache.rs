@@ -0,0 +1,121 @@
+extern crate waf;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use waf::cache::{CacheControl, Validators};
+use waf::date;
+use waf::http::Headers;
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn headers(pairs: &[(&str, &str)]) -> Headers {
+    let mut headers = Headers::new();
+    for (key, value) in pairs {
+        headers.append(key, value);
+    }
+    headers
+}
+
+#[test]
+fn test_format_date() {
+    let date = utc("1994-11-06T08:49:37Z");
+    assert_eq!(date::format(&date), "Sun, 06 Nov 1994 08:49:37 GMT");
+}
+
+#[test]
+fn test_parse_date_formats() {
+    let expected = utc("1994-11-06T08:49:37Z");
+    assert_eq!(date::parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+    assert_eq!(
+        date::parse("Sunday, 06-Nov-94 08:49:37 GMT"),
+        Some(expected)
+    );
+    assert_eq!(date::parse("Sun Nov  6 08:49:37 1994"), Some(expected));
+
+    assert_eq!(date::parse("06 Nov 1994"), None);
+    assert_eq!(date::parse("Sun, 06 Nov 1994 08:49:37 PST"), None);
+    assert_eq!(date::parse(""), None);
+}
+
+#[test]
+fn test_cache_control() {
+    let cache = CacheControl::new()
+        .public()
+        .max_age(Duration::from_secs(3600))
+        .immutable();
+    assert_eq!(cache.to_string(), "public, max-age=3600, immutable");
+    assert_eq!(CacheControl::new().no_store().to_string(), "no-store");
+}
+
+#[test]
+fn test_if_none_match() {
+    let validators = Validators::new().with_etag("\"v2\"");
+    let request = headers(&[("If-None-Match", "\"v1\", W/\"v2\"")]);
+    assert_eq!(validators.evaluate("GET", &request), Some(304));
+    assert_eq!(validators.evaluate("PUT", &request), Some(412));
+
+    let request = headers(&[("If-None-Match", "\"v1\"")]);
+    assert_eq!(validators.evaluate("GET", &request), None);
+
+    let response = validators.response(304);
+    assert_eq!(response.header("ETag"), Some("\"v2\""));
+}
+
+#[test]
+fn test_if_match() {
+    let validators = Validators::new().with_etag("\"v2\"");
+    assert_eq!(
+        validators.evaluate("PUT", &headers(&[("If-Match", "\"v2\"")])),
+        None
+    );
+    assert_eq!(
+        validators.evaluate("PUT", &headers(&[("If-Match", "\"v1\"")])),
+        Some(412)
+    );
+    assert_eq!(
+        validators.evaluate("PUT", &headers(&[("If-Match", "*")])),
+        None
+    );
+
+    // If-Match uses the strong comparison
+    let weak = Validators::new().with_etag("W/\"v2\"");
+    assert_eq!(
+        weak.evaluate("PUT", &headers(&[("If-Match", "W/\"v2\"")])),
+        Some(412)
+    );
+}
+
+#[test]
+fn test_modified_since() {
+    let last_modified = utc("2020-01-01T00:00:00Z");
+    let validators = Validators::new().with_last_modified(last_modified);
+
+    let request = headers(&[("If-Modified-Since", "Wed, 01 Jan 2020 00:00:00 GMT")]);
+    assert_eq!(validators.evaluate("GET", &request), Some(304));
+    let request = headers(&[("If-Modified-Since", "Tue, 31 Dec 2019 23:59:59 GMT")]);
+    assert_eq!(validators.evaluate("GET", &request), None);
+    // Only GET and HEAD are affected by If-Modified-Since
+    let request = headers(&[("If-Modified-Since", "Wed, 01 Jan 2020 00:00:00 GMT")]);
+    assert_eq!(validators.evaluate("POST", &request), None);
+
+    let request = headers(&[("If-Unmodified-Since", "Tue, 31 Dec 2019 23:59:59 GMT")]);
+    assert_eq!(validators.evaluate("PUT", &request), Some(412));
+    let request = headers(&[("If-Unmodified-Since", "Wed, 01 Jan 2020 00:00:00 GMT")]);
+    assert_eq!(validators.evaluate("PUT", &request), None);
+}
+
+#[test]
+fn test_if_range() {
+    let last_modified = utc("2020-01-01T00:00:00Z");
+    let validators = Validators::new()
+        .with_etag("\"v1\"")
+        .with_last_modified(last_modified);
+    assert!(validators.if_range(&Headers::new()));
+    assert!(validators.if_range(&headers(&[("If-Range", "\"v1\"")])));
+    assert!(!validators.if_range(&headers(&[("If-Range", "\"v0\"")])));
+    assert!(validators.if_range(&headers(&[("If-Range", "Wed, 01 Jan 2020 00:00:00 GMT")])));
+}
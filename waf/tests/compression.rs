@@ -0,0 +1,156 @@
+extern crate waf;
+
+use std::io::Read;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use waf::compression::{Compression, Encoding};
+use waf::http::{Connection, METHOD_GET};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn json() -> String {
+    let items: Vec<String> = (0..200)
+        .map(|i| format!("{{\"id\":{},\"name\":\"item\"}}", i))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router.add_middleware(Compression::new());
+    router.add_handler(METHOD_GET, "/items", |_conn: &mut Connection| {
+        Ok(Response::json(json()).with_header("ETag", "\"v1\""))
+    });
+    router.add_handler(METHOD_GET, "/small", |_conn: &mut Connection| {
+        Ok(Response::json("[]"))
+    });
+    router.add_handler(METHOD_GET, "/image", |_conn: &mut Connection| {
+        Ok(Response::new(200)
+            .with_header("Content-Type", "image/png")
+            .with_body(vec![0; 4096]))
+    });
+    router.add_handler(METHOD_GET, "/stream", |_conn: &mut Connection| {
+        Ok(Response::streaming(|writer| {
+            for i in 0..100 {
+                writeln!(writer, "line {}", i)?;
+                writer.flush()?;
+            }
+            Ok(())
+        })
+        .with_header("Content-Type", "text/plain"))
+    });
+    TestClient::new(router)
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(Encoding::negotiate("gzip"), Some(Encoding::Gzip));
+    assert_eq!(
+        Encoding::negotiate("deflate, gzip;q=0.5"),
+        Some(Encoding::Deflate)
+    );
+    assert_eq!(
+        Encoding::negotiate("gzip;q=0, deflate"),
+        Some(Encoding::Deflate)
+    );
+    assert_eq!(Encoding::negotiate("identity"), None);
+    assert_eq!(Encoding::negotiate("*;q=0"), None);
+    assert_eq!(Encoding::negotiate(""), None);
+    #[cfg(not(feature = "brotli"))]
+    assert_eq!(Encoding::negotiate("br, gzip;q=0.8"), Some(Encoding::Gzip));
+    #[cfg(feature = "brotli")]
+    assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+}
+
+#[test]
+fn test_gzip_and_deflate() {
+    let client = client();
+
+    let response = client
+        .get("/items")
+        .with_header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(response.header("ETag"), Some("W/\"v1\""));
+    assert!(response.body().len() < json().len());
+    let mut body = String::new();
+    GzDecoder::new(response.body())
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, json());
+
+    let response = client
+        .get("/items")
+        .with_header("Accept-Encoding", "deflate")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+    let mut body = String::new();
+    ZlibDecoder::new(response.body())
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, json());
+}
+
+#[test]
+fn test_not_compressed() {
+    let client = client();
+
+    let response = client.get("/items").send().unwrap();
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(response.text(), json());
+
+    let response = client
+        .get("/small")
+        .with_header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.text(), "[]");
+
+    let response = client
+        .get("/image")
+        .with_header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), None);
+    assert_eq!(response.body().len(), 4096);
+}
+
+#[test]
+fn test_streaming() {
+    let response = client()
+        .get("/stream")
+        .with_header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+    let mut body = String::new();
+    GzDecoder::new(response.body())
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body.lines().count(), 100);
+    assert_eq!(body.lines().last(), Some("line 99"));
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn test_brotli() {
+    let response = client()
+        .get("/items")
+        .with_header("Accept-Encoding", "gzip, br")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Content-Encoding"), Some("br"));
+    let mut body = String::new();
+    brotli::Decompressor::new(response.body(), 4096)
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, json());
+}
@@ -0,0 +1,130 @@
+extern crate waf;
+
+use std::time::Duration;
+
+use waf::cookie::{parse_cookie_header, Cookie, CookieJar, Key, SameSite};
+use waf::http::{Connection, Headers, METHOD_GET};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+#[test]
+fn test_set_cookie_header() {
+    let cookie = Cookie::new("id", "a3fWa")
+        .with_path("/")
+        .with_max_age(Duration::from_secs(3600))
+        .with_secure(true)
+        .with_http_only(true)
+        .with_same_site(SameSite::Strict);
+    assert_eq!(
+        cookie.to_string(),
+        "id=a3fWa; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=Strict"
+    );
+
+    let cookie = Cookie::new("name", "a b;c").with_same_site(SameSite::None);
+    assert_eq!(cookie.to_string(), "name=a%20b%3Bc; Secure; SameSite=None");
+
+    assert_eq!(
+        Cookie::removal("id").to_string(),
+        "id=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+}
+
+#[test]
+fn test_parse_cookie_header() {
+    assert_eq!(
+        parse_cookie_header("a=1; b=\"two\";c=a%20b; invalid; =x; d=100%"),
+        vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "two".to_string()),
+            ("c".to_string(), "a b".to_string()),
+            ("d".to_string(), "100%".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_jar() {
+    let mut headers = Headers::new();
+    headers.append("Cookie", "theme=dark; lang=en");
+    headers.append("Cookie", "tz=UTC");
+    let mut jar = CookieJar::from_headers(&headers);
+    assert_eq!(jar.get("theme"), Some("dark"));
+    assert_eq!(jar.get("tz"), Some("UTC"));
+    assert_eq!(jar.iter().count(), 3);
+
+    jar.add(Cookie::new("theme", "light"));
+    jar.remove(Cookie::new("lang", "").with_path("/"));
+    assert_eq!(jar.get("theme"), Some("light"));
+    assert_eq!(jar.get("lang"), None);
+
+    let mut response = Response::ok();
+    jar.apply(&mut response);
+    let set_cookies: Vec<&str> = response.headers().get_all("Set-Cookie").collect();
+    assert_eq!(set_cookies.len(), 2);
+    assert_eq!(set_cookies[0], "theme=light");
+    assert!(set_cookies[1].starts_with("lang=; Path=/; Max-Age=0"));
+}
+
+#[test]
+fn test_signed_and_private() {
+    let key = Key::new(&[7; 32]).unwrap();
+    assert!(Key::new(b"short").is_err());
+
+    let mut jar = CookieJar::new();
+    jar.add_signed(Cookie::new("user", "alice"), &key);
+    jar.add_private(Cookie::new("secret", "s3cr3t"), &key);
+    let signed = jar.get("user").unwrap().to_string();
+    let private = jar.get("secret").unwrap().to_string();
+    assert!(signed.ends_with(".alice"));
+    assert!(!private.contains("s3cr3t"));
+
+    // As sent back by the client
+    let mut headers = Headers::new();
+    headers.append(
+        "Cookie",
+        &format!("user={}; secret={}; moved={}", signed, private, signed),
+    );
+    let jar = CookieJar::from_headers(&headers);
+    assert_eq!(jar.get_signed("user", &key).as_deref(), Some("alice"));
+    assert_eq!(jar.get_private("secret", &key).as_deref(), Some("s3cr3t"));
+    assert_eq!(jar.get_signed("moved", &key), None);
+    assert_eq!(jar.get_signed("user", &Key::generate().unwrap()), None);
+    assert_eq!(jar.get_private("secret", &Key::generate().unwrap()), None);
+
+    let mut headers = Headers::new();
+    headers.append(
+        "Cookie",
+        &format!("user={}", signed.replace("alice", "admin")),
+    );
+    assert_eq!(
+        CookieJar::from_headers(&headers).get_signed("user", &key),
+        None
+    );
+}
+
+#[test]
+fn test_connection_cookies() {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/", |conn: &mut Connection| {
+        let visits: u32 = conn
+            .cookie("visits")
+            .and_then(|visits| visits.parse().ok())
+            .unwrap_or(0);
+        Ok(Response::text(visits.to_string())
+            .with_cookie(&Cookie::new("visits", &(visits + 1).to_string())))
+    });
+    let client = TestClient::new(router);
+
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.text(), "0");
+    assert_eq!(response.header("Set-Cookie"), Some("visits=1"));
+
+    let response = client
+        .get("/")
+        .with_header("Cookie", "visits=5")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "5");
+    assert_eq!(response.header("Set-Cookie"), Some("visits=6"));
+}
@@ -0,0 +1,133 @@
+extern crate waf;
+
+use std::time::Duration;
+
+use waf::cors::Cors;
+use waf::http::{Connection, METHOD_DELETE, METHOD_GET, METHOD_OPTIONS, METHOD_PUT};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn client(cors: Cors) -> TestClient {
+    let mut router = Router::new();
+    router.add_middleware(cors);
+    router.add_handler(METHOD_GET, "/items", |_conn: &mut Connection| {
+        Ok(Response::json("[]").with_header("X-Total", "0"))
+    });
+    router.add_handler(METHOD_PUT, "/items", |_conn: &mut Connection| {
+        Ok(Response::new(204))
+    });
+    TestClient::new(router)
+}
+
+fn preflight(client: &TestClient, origin: &str, method: &str) -> waf::testing::TestResponse {
+    client
+        .request(METHOD_OPTIONS, "/items")
+        .with_header("Origin", origin)
+        .with_header("Access-Control-Request-Method", method)
+        .with_header("Access-Control-Request-Headers", "content-type, x-token")
+        .send()
+        .unwrap()
+}
+
+#[test]
+fn test_preflight() {
+    let client = client(
+        Cors::new()
+            .with_origins(&["https://app.example.com"])
+            .with_headers(&["Content-Type"])
+            .with_max_age(Duration::from_secs(600)),
+    );
+
+    let response = preflight(&client, "https://app.example.com", "PUT");
+    assert_eq!(response.status(), 204);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Methods"),
+        Some("GET, HEAD, OPTIONS, PUT")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Headers"),
+        Some("Content-Type")
+    );
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+    assert!(response.header("Vary").unwrap().contains("Origin"));
+
+    // The route has no DELETE handler.
+    let response = preflight(&client, "https://app.example.com", METHOD_DELETE);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+    let response = preflight(&client, "https://evil.example.com", "PUT");
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+    let response = client
+        .request(METHOD_OPTIONS, "/missing")
+        .with_header("Origin", "https://app.example.com")
+        .with_header("Access-Control-Request-Method", "GET")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+}
+
+#[test]
+fn test_actual_request() {
+    let client = client(
+        Cors::new()
+            .with_origins(&["https://app.example.com"])
+            .with_exposed_headers(&["X-Total"])
+            .with_credentials(true),
+    );
+    let response = client
+        .get("/items")
+        .with_header("Origin", "https://APP.example.com")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://APP.example.com")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert_eq!(
+        response.header("Access-Control-Expose-Headers"),
+        Some("X-Total")
+    );
+    assert_eq!(response.header("Vary"), Some("Origin"));
+
+    let response = client
+        .get("/items")
+        .with_header("Origin", "https://evil.example.com")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+    let response = client.get("/items").send().unwrap();
+    assert_eq!(response.header("Vary"), Some("Origin"));
+}
+
+#[test]
+fn test_any_origin() {
+    let client = client(Cors::new().with_any_origin().with_any_header());
+    let response = client
+        .get("/items")
+        .with_header("Origin", "https://anywhere.example")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(response.header("Vary"), None);
+
+    let response = preflight(&client, "https://anywhere.example", "GET");
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(
+        response.header("Access-Control-Allow-Headers"),
+        Some("content-type, x-token")
+    );
+}
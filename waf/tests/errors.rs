@@ -0,0 +1,99 @@
+extern crate waf;
+
+use std::io::Error;
+use std::sync::{Arc, Mutex};
+
+use waf::http::{Connection, HttpError, METHOD_DELETE, METHOD_GET, METHOD_POST};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/items", |_conn: &mut Connection| {
+        Ok(Response::text("items"))
+    });
+    router.add_handler(METHOD_POST, "/items", |_conn: &mut Connection| {
+        Ok(Response::new(201))
+    });
+    router.add_handler(METHOD_GET, "/fail", |_conn: &mut Connection| {
+        Err(Error::other("database is down"))
+    });
+    router.add_handler(METHOD_GET, "/forbidden", |_conn: &mut Connection| {
+        Err(HttpError::new(403, "not yours").into())
+    });
+    router.add_handler(METHOD_GET, "/panic", |_conn: &mut Connection| {
+        panic!("boom");
+    });
+    router
+}
+
+#[test]
+fn test_custom_not_found() {
+    let mut router = router();
+    router.set_not_found_handler(|conn: &mut Connection| {
+        Ok(Response::new(404).with_body(format!("no page at {}", conn.path)))
+    });
+    let client = TestClient::new(router);
+
+    let response = client.get("/missing").send().unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.text(), "no page at /missing");
+}
+
+#[test]
+fn test_method_not_allowed_lists_methods() {
+    let client = TestClient::new(router());
+    let response = client.delete("/items").send().unwrap();
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS, POST"));
+
+    let mut router = router();
+    router.set_method_not_allowed_handler(|_conn: &mut Connection| {
+        Ok(Response::new(405).with_body("nope"))
+    });
+    let client = TestClient::new(router);
+    let response = client.request(METHOD_DELETE, "/items").send().unwrap();
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS, POST"));
+    assert_eq!(response.text(), "nope");
+}
+
+#[test]
+fn test_error_handler() {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let mut router = router();
+    let lines = logged.clone();
+    router.set_error_log(move |line: &str| lines.lock().unwrap().push(line.to_string()));
+    router.set_error_handler(|_conn: &mut Connection, status: u16, error: &Error| {
+        Response::new(status).with_body(format!("{}: {}", status, error))
+    });
+    let client = TestClient::new(router);
+
+    let response = client.get("/fail").send().unwrap();
+    assert_eq!(response.status(), 500);
+    assert_eq!(response.text(), "500: database is down");
+    assert_eq!(response.header("Connection"), Some("close"));
+
+    let response = client.get("/forbidden").send().unwrap();
+    assert_eq!(response.status(), 403);
+    assert_eq!(response.text(), "403: 403 Forbidden: not yours");
+
+    // Only errors without a status of their own are logged.
+    let logged = logged.lock().unwrap();
+    assert_eq!(logged.len(), 1);
+    assert!(logged[0].contains("database is down"));
+}
+
+#[test]
+fn test_panic_returns_500() {
+    let mut router = router();
+    router.set_error_log(|_line: &str| {});
+    let client = TestClient::new(router);
+
+    let response = client.get("/panic").send().unwrap();
+    assert_eq!(response.status(), 500);
+
+    let response = client.get("/items").send().unwrap();
+    assert_eq!(response.status(), 200);
+}
@@ -0,0 +1,210 @@
+extern crate waf;
+
+use std::io::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use waf::extract::{handler, Json, Path, Query, State};
+use waf::http::{Connection, Headers, Limits, METHOD_GET, METHOD_POST, METHOD_PUT};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Item {
+    name: String,
+    price: u32,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    offset: usize,
+    limit: Option<usize>,
+}
+
+struct Store {
+    items: Mutex<Vec<Item>>,
+}
+
+fn list_items(State(store): State<Store>, Query(page): Query<Page>) -> Result<Response> {
+    let items = store.items.lock().unwrap();
+    let page: Vec<Item> = items
+        .iter()
+        .skip(page.offset)
+        .take(page.limit.unwrap_or(10))
+        .cloned()
+        .collect();
+    Json(page).into_response()
+}
+
+fn add_item(State(store): State<Store>, Json(item): Json<Item>) -> Result<Response> {
+    store.items.lock().unwrap().push(item);
+    Ok(Response::new(201))
+}
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router.add_state(Store {
+        items: Mutex::new(Vec::new()),
+    });
+    router.add_state(AtomicUsize::new(0));
+    router.add_handler(METHOD_GET, "/items", handler(list_items));
+    router.add_handler(METHOD_POST, "/items", handler(add_item));
+    router.add_handler(
+        METHOD_PUT,
+        "/items/:id<uint>",
+        handler(|Path(id): Path<u64>, body: String| {
+            Ok(Response::text(format!("{}: {}", id, body)))
+        }),
+    );
+    router.add_handler(
+        METHOD_GET,
+        "/users/:user/posts/:post",
+        handler(
+            |Path((user, post)): Path<(String, u32)>, headers: Headers| {
+                let agent = headers.get("User-Agent").unwrap_or("-").to_string();
+                Ok(Response::text(format!("{} {} {}", user, post, agent)))
+            },
+        ),
+    );
+    router.add_handler(METHOD_GET, "/hits", |conn: &mut Connection| {
+        let hits = conn.state::<AtomicUsize>().unwrap();
+        Ok(Response::text(
+            (hits.fetch_add(1, Ordering::SeqCst) + 1).to_string(),
+        ))
+    });
+    TestClient::new(router)
+}
+
+#[test]
+fn test_state_and_json() {
+    let client = client();
+    for (name, price) in &[("apple", 100), ("pear", 150), ("plum", 80)] {
+        let response = client
+            .post("/items")
+            .with_header("Content-Type", "application/json")
+            .with_body(format!("{{\"name\":\"{}\",\"price\":{}}}", name, price))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 201);
+    }
+
+    let response = client.get("/items?offset=1&limit=1").send().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "[{\"name\":\"pear\",\"price\":150}]");
+
+    assert_eq!(client.get("/hits").send().unwrap().text(), "1");
+    assert_eq!(client.get("/hits").send().unwrap().text(), "2");
+}
+
+#[test]
+fn test_path() {
+    let client = client();
+    let response = client.put("/items/7").with_body("renamed").send().unwrap();
+    assert_eq!(response.text(), "7: renamed");
+
+    let response = client
+        .get("/users/alice/posts/3")
+        .with_header("User-Agent", "test")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "alice 3 test");
+    assert_eq!(
+        client.get("/users/alice/posts/x").send().unwrap().status(),
+        400
+    );
+}
+
+#[test]
+fn test_rejections() {
+    let client = client();
+    let response = client
+        .post("/items")
+        .with_header("Content-Type", "text/plain")
+        .with_body("{}")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 415);
+
+    let response = client
+        .post("/items")
+        .with_header("Content-Type", "application/json; charset=utf-8")
+        .with_body("{\"name\":\"fig\"}")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    assert_eq!(client.get("/items").send().unwrap().status(), 400);
+    assert_eq!(client.get("/items?offset=x").send().unwrap().status(), 400);
+}
+
+#[test]
+fn test_missing_state() {
+    let mut router = Router::new();
+    router.set_error_log(|_line: &str| {});
+    router.add_handler(
+        METHOD_GET,
+        "/",
+        handler(|_config: State<String>| Ok(Response::ok())),
+    );
+    let response = TestClient::new(router).get("/").send().unwrap();
+    assert_eq!(response.status(), 500);
+}
+
+#[test]
+fn test_optional() {
+    let mut router = Router::new();
+    router.set_error_log(|_line: &str| {});
+    router.set_limits(Limits {
+        max_body_size: Some(64),
+        ..Limits::default()
+    });
+    router.add_handler(
+        METHOD_POST,
+        "/",
+        handler(|config: Option<State<String>>, item: Option<Json<Item>>| {
+            Ok(Response::text(format!(
+                "{} {}",
+                config.is_some(),
+                item.map_or("-".to_string(), |Json(item)| item.name)
+            )))
+        }),
+    );
+    let client = TestClient::new(router);
+
+    // Missing state and no body at all are None
+    let response = client.post("/").send().unwrap();
+    assert_eq!(response.text(), "false -");
+
+    let response = client
+        .post("/")
+        .with_header("Content-Type", "application/json")
+        .with_body("{\"name\":\"fig\",\"price\":3}")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "false fig");
+
+    // A body that is there but can't be used still answers the request
+    let response = client
+        .post("/")
+        .with_header("Content-Type", "text/plain")
+        .with_body("fig")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 415);
+    let response = client
+        .post("/")
+        .with_header("Content-Type", "application/json")
+        .with_body("{\"name\":\"fig\"}")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let response = client
+        .post("/")
+        .with_header("Content-Type", "application/json")
+        .with_body(format!("{{\"name\":\"{}\",\"price\":3}}", "f".repeat(100)))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 413);
+}
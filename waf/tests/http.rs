@@ -0,0 +1,84 @@
+extern crate waf;
+
+use std::io::{BufReader, Read, Write};
+
+use waf::http::{ChunkedReader, ChunkedWriter};
+
+#[test]
+fn test_chunked_reader() {
+    let input = "4\r\nWiki\r\n5;name=value\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\n\r\n";
+    let mut reader = ChunkedReader::new(BufReader::new(input.as_bytes()));
+    let mut body = String::new();
+    reader.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "Wikipedia in\r\n\r\nchunks.");
+    assert!(reader.trailers().unwrap().is_empty());
+}
+
+#[test]
+fn test_chunked_reader_with_trailers() {
+    let input = "3\r\nabc\r\n0\r\nExpires: never\r\nX-Checksum: 42\r\n\r\nGET / HTTP/1.1";
+    let mut reader = ChunkedReader::new(BufReader::new(input.as_bytes()));
+    assert!(reader.trailers().is_none());
+    let mut body = String::new();
+    reader.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "abc");
+
+    let trailers = reader.trailers().unwrap();
+    assert_eq!(trailers.get("Expires").unwrap(), "never");
+    assert_eq!(trailers.get("X-Checksum").unwrap(), "42");
+
+    let mut rest = String::new();
+    reader.into_inner().read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "GET / HTTP/1.1");
+}
+
+#[test]
+fn test_chunked_reader_invalid_size() {
+    let mut reader = ChunkedReader::new(BufReader::new("xyz\r\nabc\r\n0\r\n\r\n".as_bytes()));
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_chunked_reader_unexpected_eof() {
+    let mut reader = ChunkedReader::new(BufReader::new("a\r\nabc".as_bytes()));
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_chunked_writer() {
+    let mut writer = ChunkedWriter::new(Vec::new());
+    writer.write_all(b"Hello, ").unwrap();
+    writer.write_all(b"").unwrap();
+    writer.write_all(b"World!").unwrap();
+    let output = writer.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "7\r\nHello, \r\n6\r\nWorld!\r\n0\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_chunked_writer_with_trailers() {
+    let mut writer = ChunkedWriter::new(Vec::new());
+    writer.write_all(b"0123456789abcdef").unwrap();
+    let output = writer.finish_with_trailers(&[("X-Count", "16")]).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "10\r\n0123456789abcdef\r\n0\r\nX-Count: 16\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_chunked_round_trip() {
+    let mut writer = ChunkedWriter::new(Vec::new());
+    for i in 0..100 {
+        writeln!(writer, "line {}", i).unwrap();
+    }
+    let encoded = writer.finish().unwrap();
+
+    let mut reader = ChunkedReader::new(BufReader::new(&encoded[..]));
+    let mut decoded = String::new();
+    reader.read_to_string(&mut decoded).unwrap();
+    assert_eq!(decoded.lines().count(), 100);
+    assert_eq!(decoded.lines().last(), Some("line 99"));
+}
@@ -0,0 +1,350 @@
+extern crate waf;
+
+use std::io::{self, Cursor, Read};
+
+use waf::http::{error_status, request_length, Connection, Headers, Limits, RequestScanner};
+
+fn parse(input: &[u8]) -> io::Result<Connection> {
+    parse_with_limits(input, Limits::default())
+}
+
+fn parse_with_limits(input: &[u8], limits: Limits) -> io::Result<Connection> {
+    let mut conn = Connection::from_io(Cursor::new(input.to_vec()), io::sink());
+    conn.set_limits(limits);
+    assert!(conn.next_request()?);
+    Ok(conn)
+}
+
+fn status_of(input: &[u8]) -> Option<u16> {
+    error_status(&parse(input).err()?)
+}
+
+#[test]
+fn test_parse_request() {
+    let mut conn = parse(
+        b"POST /users?name=a%20b HTTP/1.1\r\nHost: example.com\r\ncontent-length: 5\r\n\r\nhello",
+    )
+    .unwrap();
+    assert_eq!(conn.method, "POST");
+    assert_eq!(conn.path, "/users");
+    assert_eq!(conn.query.as_deref(), Some("name=a%20b"));
+    assert_eq!(conn.version, "HTTP/1.1");
+    assert_eq!(conn.header("Content-Length"), Some("5"));
+    assert_eq!(conn.header("HOST"), Some("example.com"));
+
+    let mut body = String::new();
+    conn.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+}
+
+#[test]
+fn test_empty_input_is_not_an_error() {
+    let mut conn = Connection::from_io(io::empty(), io::sink());
+    assert!(!conn.next_request().unwrap());
+
+    let mut conn = Connection::from_io(Cursor::new(b"\r\n\r\n".to_vec()), io::sink());
+    assert!(!conn.next_request().unwrap());
+}
+
+#[test]
+fn test_leading_empty_lines() {
+    let conn = parse(b"\r\n\nGET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    assert_eq!(conn.path, "/");
+
+    let endless = b"\r\n".repeat(1000);
+    assert_eq!(status_of(&endless), Some(400));
+    let err = request_length(&endless, &Limits::default()).err().unwrap();
+    assert_eq!(error_status(&err), Some(400));
+}
+
+#[test]
+fn test_repeated_headers() {
+    let conn = parse(
+        b"GET / HTTP/1.1\r\nHost: a\r\nAccept: text/html\r\naccept: application/json\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(conn.header("Accept"), Some("text/html"));
+    let all: Vec<&str> = conn.headers.get_all("ACCEPT").collect();
+    assert_eq!(all, vec!["text/html", "application/json"]);
+}
+
+#[test]
+fn test_headers() {
+    let mut headers = Headers::new();
+    headers.append("Connection", "keep-alive, Upgrade");
+    headers.append("Set-Cookie", "a=1");
+    headers.append("set-cookie", "b=2");
+    assert!(headers.has_token("connection", "upgrade"));
+    assert!(!headers.has_token("Connection", "close"));
+    assert_eq!(headers.len(), 3);
+
+    headers.set("SET-COOKIE", "c=3");
+    assert_eq!(
+        headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+        vec!["c=3"]
+    );
+    headers.remove("connection");
+    assert!(!headers.contains("Connection"));
+    assert_eq!(headers.len(), 1);
+}
+
+#[test]
+fn test_absolute_form_target() {
+    let conn =
+        parse(b"GET http://example.com/a/b?c=d HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+    assert_eq!(conn.path, "/a/b");
+    assert_eq!(conn.query.as_deref(), Some("c=d"));
+
+    let conn = parse(b"GET http://example.com HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+    assert_eq!(conn.path, "/");
+}
+
+#[test]
+fn test_http_10_without_host() {
+    let conn = parse(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(conn.version, "HTTP/1.0");
+    assert!(!conn.keep_alive());
+}
+
+#[test]
+fn test_bad_requests() {
+    let cases: &[&[u8]] = &[
+        b"GET  / HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"GET / HTTP/1.1 \r\nHost: a\r\n\r\n",
+        b"GET /\r\nHost: a\r\n\r\n",
+        b"G(T / HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"GET / HTTP/1.x\r\nHost: a\r\n\r\n",
+        b"GET / http/1.1\r\nHost: a\r\n\r\n",
+        b"GET / HTTP/1.1\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX-Folded: a\r\n b\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nNo colon\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX: a\rb\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX: a\0b\r\n\r\n",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1, 2\r\n\r\nab",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nabc",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+    ];
+    for case in cases {
+        assert_eq!(
+            status_of(case),
+            Some(400),
+            "{}",
+            String::from_utf8_lossy(case)
+        );
+    }
+
+    // Agreeing duplicates are fine
+    let conn = parse(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 3\r\n\r\nabc").unwrap();
+    assert_eq!(conn.header("Content-Length"), Some("3, 3"));
+}
+
+#[test]
+fn test_unsupported_version_and_encoding() {
+    assert_eq!(status_of(b"GET / HTTP/2.0\r\nHost: a\r\n\r\n"), Some(505));
+    assert_eq!(
+        status_of(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+        Some(501)
+    );
+}
+
+#[test]
+fn test_limits() {
+    let limits = Limits {
+        max_request_line: 64,
+        max_header_size: 128,
+        max_headers: 4,
+        max_body_size: Some(10),
+    };
+    let long_path = format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", "a".repeat(100));
+    let err = parse_with_limits(long_path.as_bytes(), limits)
+        .err()
+        .unwrap();
+    assert_eq!(error_status(&err), Some(414));
+
+    let long_header = format!(
+        "GET / HTTP/1.1\r\nHost: a\r\nX: {}\r\n\r\n",
+        "a".repeat(200)
+    );
+    let err = parse_with_limits(long_header.as_bytes(), limits)
+        .err()
+        .unwrap();
+    assert_eq!(error_status(&err), Some(431));
+
+    let many_headers = "GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n";
+    let err = parse_with_limits(many_headers.as_bytes(), limits)
+        .err()
+        .unwrap();
+    assert_eq!(error_status(&err), Some(431));
+
+    let large_body = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world";
+    let err = parse_with_limits(large_body.as_bytes(), limits)
+        .err()
+        .unwrap();
+    assert_eq!(error_status(&err), Some(413));
+
+    let large_chunked =
+        "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n";
+    let mut conn = parse_with_limits(large_chunked.as_bytes(), limits).unwrap();
+    let err = conn.read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(error_status(&err), Some(413));
+}
+
+#[test]
+fn test_request_length() {
+    let limits = Limits::default();
+    let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET";
+    for end in 0..request.len() - 3 {
+        assert_eq!(request_length(&request[..end], &limits).unwrap(), None);
+    }
+    assert_eq!(
+        request_length(request, &limits).unwrap(),
+        Some(request.len() - 3)
+    );
+
+    let chunked =
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+    assert_eq!(
+        request_length(chunked, &limits).unwrap(),
+        Some(chunked.len())
+    );
+    assert_eq!(
+        request_length(&chunked[..chunked.len() - 1], &limits).unwrap(),
+        None
+    );
+
+    // A request line that can't fit the limit is rejected before it is complete
+    let endless = vec![b'a'; 10000];
+    let err = request_length(&endless, &limits).err().unwrap();
+    assert_eq!(error_status(&err), Some(414));
+}
+
+#[test]
+fn test_request_scanner() {
+    let limits = Limits::default();
+    let requests: &[&[u8]] = &[
+        b"\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\nGET",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET",
+        b"POST / HTTP/1.1\nHost: a\nTransfer-Encoding: chunked\n\n5;x=y\nhello\n0\nT: 1\n\nGET",
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\n\r\nGET",
+    ];
+    // Fed a byte at a time, the scanner finds the same end as parsing the whole request
+    for request in requests {
+        let len = request.len() - 3;
+        let mut scanner = RequestScanner::new(limits);
+        for end in 0..len {
+            assert_eq!(scanner.scan(&request[..end]).unwrap(), None);
+        }
+        assert_eq!(scanner.scan(request).unwrap(), Some(len));
+        assert!(scanner.is_head_complete());
+        assert_eq!(request_length(request, &limits).unwrap(), Some(len));
+    }
+
+    let mut scanner = RequestScanner::new(limits);
+    let bad_chunk = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd";
+    assert_eq!(
+        scanner.scan(&bad_chunk[..bad_chunk.len() - 1]).unwrap(),
+        None
+    );
+    let err = scanner.scan(bad_chunk).err().unwrap();
+    assert_eq!(error_status(&err), Some(400));
+
+    let long_header = format!("GET / HTTP/1.1\r\nHost: a\r\nX: {}", "a".repeat(80000));
+    let err = request_length(long_header.as_bytes(), &limits)
+        .err()
+        .unwrap();
+    assert_eq!(error_status(&err), Some(431));
+}
+
+// xorshift, so that failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn mutate(rng: &mut Rng, input: &[u8]) -> Vec<u8> {
+    const INTERESTING: &[u8] = b" \t\r\n:;,0\x00\x7f\xff";
+    let mut output = input.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        let i = rng.below(output.len() + 1);
+        match rng.below(4) {
+            0 if i < output.len() => {
+                output.remove(i);
+            }
+            1 if i < output.len() => output[i] = rng.next() as u8,
+            2 => output.insert(i, INTERESTING[rng.below(INTERESTING.len())]),
+            _ => {
+                let end = (i + rng.below(16)).min(output.len());
+                let copy = output[i.min(end)..end].to_vec();
+                output.splice(i..i, copy);
+            }
+        }
+    }
+    output
+}
+
+#[test]
+fn test_fuzz_parser() {
+    let seeds: &[&[u8]] = &[
+        b"GET /index.html?a=b HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n",
+        b"POST /form HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello",
+        b"PUT /x HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5;x=y\r\nhello\r\n0\r\nT: 1\r\n\r\n",
+        b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+    ];
+    let limits = Limits {
+        max_request_line: 48,
+        max_header_size: 96,
+        max_headers: 5,
+        max_body_size: Some(64),
+    };
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..20000 {
+        let seed = seeds[rng.below(seeds.len())];
+        let input = mutate(&mut rng, seed);
+
+        let mut conn = Connection::from_io(Cursor::new(input.clone()), io::sink());
+        conn.set_limits(limits);
+        let result = conn
+            .next_request()
+            .and_then(|_| conn.read_to_end(&mut Vec::new()));
+        if let Err(e) = result {
+            match error_status(&e) {
+                Some(status) => assert!(
+                    [400, 413, 414, 431, 501, 505].contains(&status),
+                    "{} for {:?}",
+                    status,
+                    String::from_utf8_lossy(&input)
+                ),
+                None => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            }
+        }
+
+        // The sans-IO check must agree on where the first request ends, whether or not the
+        // input arrives in one piece
+        let whole = request_length(&input, &limits);
+        if let Ok(Some(len)) = whole {
+            assert!(len <= input.len());
+        }
+        let mut scanner = RequestScanner::new(limits);
+        if let Ok(None) = scanner.scan(&input[..rng.below(input.len() + 1)]) {
+            let pieces = scanner.scan(&input);
+            match (&whole, &pieces) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b),
+                (Err(a), Err(b)) => assert_eq!(error_status(a), error_status(b)),
+                _ => panic!("{:?} != {:?} for {:?}", whole, pieces, input),
+            }
+        }
+    }
+}
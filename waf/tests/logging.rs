@@ -0,0 +1,127 @@
+extern crate waf;
+
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use waf::http::{Connection, METHOD_GET};
+use waf::logging::{AccessLog, LogFormat, RequestId};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn client(format: LogFormat, request_id: RequestId) -> (TestClient, Arc<Mutex<Vec<String>>>) {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = {
+        let lines = lines.clone();
+        move |line: &str| lines.lock().unwrap().push(line.to_string())
+    };
+
+    let mut router = Router::new();
+    router.add_middleware(request_id);
+    router.add_middleware(AccessLog::new(format, sink));
+    router.add_handler(METHOD_GET, "/hello", |conn: &mut Connection| {
+        Ok(Response::text(format!("hello {}", conn.request_id())))
+    });
+    (TestClient::new(router), lines)
+}
+
+#[test]
+fn test_common_log_format() {
+    let (client, lines) = client(LogFormat::Common, RequestId::new());
+    client.get("/hello?x=1").send().unwrap();
+    client.get("/missing").send().unwrap();
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("- - - ["), "{}", lines[0]);
+    assert!(
+        lines[0].ends_with("] \"GET /hello?x=1 HTTP/1.1\" 200 22"),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[1].ends_with("\"GET /missing HTTP/1.1\" 404 0"));
+}
+
+#[test]
+fn test_combined_log_format() {
+    let (client, lines) = client(LogFormat::Combined, RequestId::new());
+    client
+        .get("/hello")
+        .with_header("User-Agent", "test \"agent\"")
+        .send()
+        .unwrap();
+
+    let lines = lines.lock().unwrap();
+    assert!(
+        lines[0].ends_with(" 200 22 \"-\" \"test \\\"agent\\\"\""),
+        "{}",
+        lines[0]
+    );
+}
+
+#[test]
+fn test_json_log_format() {
+    let (client, lines) = client(LogFormat::Json, RequestId::new().with_trust_incoming(true));
+    let response = client
+        .get("/hello")
+        .with_header("X-Request-Id", "abc-123")
+        .with_header("User-Agent", "quote \" and \\ backslash")
+        .send()
+        .unwrap();
+    assert_eq!(response.header("X-Request-Id"), Some("abc-123"));
+    assert_eq!(response.text(), "hello abc-123");
+
+    let lines = lines.lock().unwrap();
+    let line: Value = serde_json::from_str(&lines[0]).unwrap();
+    assert!(line["time"].as_str().unwrap().ends_with('Z'), "{}", line);
+    assert_eq!(line["request_id"], "abc-123");
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], "/hello");
+    assert_eq!(line["query"], Value::Null);
+    assert_eq!(line["version"], "HTTP/1.1");
+    assert_eq!(line["status"], 200);
+    assert_eq!(line["size"], 13);
+    assert!(line["duration_ms"].as_f64().unwrap() >= 0.0);
+    assert_eq!(line["user_agent"], "quote \" and \\ backslash");
+    assert!(line.get("referer").is_none());
+}
+
+#[test]
+fn test_generated_request_ids() {
+    let (client, _) = client(LogFormat::Json, RequestId::new());
+    let first = client
+        .get("/hello")
+        .with_header("X-Request-Id", "spoofed")
+        .send()
+        .unwrap();
+    let second = client.get("/hello").send().unwrap();
+
+    let first = first.header("X-Request-Id").unwrap().to_string();
+    let second = second.header("X-Request-Id").unwrap().to_string();
+    assert_ne!(first, "spoofed");
+    assert_eq!(first.len(), 16);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_invalid_incoming_request_ids() {
+    let (client, _) = client(LogFormat::Json, RequestId::new().with_trust_incoming(true));
+    for id in &["has space", "quote\"", "tab\tseparated", &"a".repeat(129)] {
+        let response = client
+            .get("/hello")
+            .with_header("X-Request-Id", id)
+            .send()
+            .unwrap();
+        let generated = response.header("X-Request-Id").unwrap();
+        assert_ne!(generated, *id);
+        assert_eq!(generated.len(), 16);
+    }
+    let id = "a".repeat(128);
+    let response = client
+        .get("/hello")
+        .with_header("X-Request-Id", &id)
+        .send()
+        .unwrap();
+    assert_eq!(response.header("X-Request-Id"), Some(id.as_str()));
+}
@@ -0,0 +1,190 @@
+extern crate waf;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use waf::http::{Connection, METHOD_GET};
+use waf::middleware::{Middleware, Next};
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+type Trace = Arc<Mutex<Vec<String>>>;
+
+// Records when the chain enters and leaves it, and marks the response on the way out.
+fn traced(trace: &Trace, name: &'static str) -> impl Middleware {
+    let trace = trace.clone();
+    move |conn: &mut Connection, next: Next| {
+        trace.lock().unwrap().push(format!("enter {}", name));
+        let mut response = next.run(conn)?;
+        trace.lock().unwrap().push(format!("leave {}", name));
+        response.append_header("X-Trace", name);
+        Ok(response)
+    }
+}
+
+fn handler(trace: &Trace) -> impl Fn(&mut Connection) -> std::io::Result<Response> {
+    let trace = trace.clone();
+    move |conn: &mut Connection| {
+        trace.lock().unwrap().push(format!("handle {}", conn.path));
+        Ok(Response::text("handled"))
+    }
+}
+
+fn take(trace: &Trace) -> Vec<String> {
+    trace.lock().unwrap().drain(..).collect()
+}
+
+#[test]
+fn test_ordering() {
+    let trace = Trace::default();
+    let mut router = Router::new();
+    router.add_middleware(traced(&trace, "first"));
+    router.add_middleware(traced(&trace, "second"));
+    router.add_handler(METHOD_GET, "/", handler(&trace));
+    let client = TestClient::new(router);
+
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.text(), "handled");
+    assert_eq!(
+        take(&trace),
+        [
+            "enter first",
+            "enter second",
+            "handle /",
+            "leave second",
+            "leave first"
+        ]
+    );
+    // The innermost middleware sees the response first
+    let marks: Vec<&str> = response.headers().get_all("X-Trace").collect();
+    assert_eq!(marks, ["second", "first"]);
+}
+
+#[test]
+fn test_short_circuit() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let trace = Trace::default();
+    let mut router = Router::new();
+    router.add_middleware(traced(&trace, "outer"));
+    router.add_middleware(|conn: &mut Connection, next: Next| {
+        if conn.header("Authorization").is_none() {
+            return Ok(Response::new(401));
+        }
+        next.run(conn)
+    });
+    router.add_middleware(traced(&trace, "inner"));
+    {
+        let calls = calls.clone();
+        router.add_handler(METHOD_GET, "/", move |_conn: &mut Connection| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Response::text("secret"))
+        });
+    }
+    let client = TestClient::new(router);
+
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    // Middleware before the short-circuit still sees the response
+    assert_eq!(take(&trace), ["enter outer", "leave outer"]);
+    assert_eq!(response.header("X-Trace"), Some("outer"));
+
+    let response = client
+        .get("/")
+        .with_header("Authorization", "Bearer token")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "secret");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        take(&trace),
+        ["enter outer", "enter inner", "leave inner", "leave outer"]
+    );
+}
+
+#[test]
+fn test_route_middleware() {
+    let trace = Trace::default();
+    let mut router = Router::new();
+    router.add_middleware(traced(&trace, "router"));
+    router
+        .add_handler(METHOD_GET, "/admin", handler(&trace))
+        .add_middleware(traced(&trace, "route"));
+    router.add_handler(METHOD_GET, "/public", handler(&trace));
+    let client = TestClient::new(router);
+
+    // Router middleware runs before the route's own
+    client.get("/admin").send().unwrap();
+    assert_eq!(
+        take(&trace),
+        [
+            "enter router",
+            "enter route",
+            "handle /admin",
+            "leave route",
+            "leave router"
+        ]
+    );
+
+    // Route middleware applies to its route only
+    client.get("/public").send().unwrap();
+    assert_eq!(
+        take(&trace),
+        ["enter router", "handle /public", "leave router"]
+    );
+
+    // Router middleware also sees requests no route matches
+    let response = client.get("/missing").send().unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(take(&trace), ["enter router", "leave router"]);
+}
+
+#[test]
+fn test_errors_pass_through_middleware() {
+    let trace = Trace::default();
+    let mut router = Router::new();
+    router.add_middleware(traced(&trace, "outer"));
+    router.add_handler(METHOD_GET, "/", |_conn: &mut Connection| {
+        Err(std::io::Error::other("broken"))
+    });
+    router.set_error_log(|_: &str| {});
+    let client = TestClient::new(router);
+
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.status(), 500);
+    // The error skips the rest of the middleware on its way out
+    assert_eq!(take(&trace), ["enter outer"]);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_read_headers() {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/", |conn: &mut Connection| {
+        let mut names = Vec::new();
+        while let Some((key, _)) = conn.read_header()? {
+            names.push(key);
+        }
+        let headers = conn.read_headers()?;
+        Ok(Response::text(format!(
+            "{} {}",
+            names.join(","),
+            headers["X-Repeated"]
+        )))
+    });
+    let client = TestClient::new(router);
+
+    let response = client
+        .get("/")
+        .with_header("X-Repeated", "one")
+        .with_header("X-Repeated", "two")
+        .send()
+        .unwrap();
+    assert!(
+        response.text().contains("X-Repeated,X-Repeated"),
+        "{}",
+        response.text()
+    );
+    assert!(response.text().ends_with(" two"));
+}
@@ -0,0 +1,47 @@
+extern crate waf;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Barrier};
+
+use waf::pool::WorkerPool;
+
+#[test]
+fn test_runs_all_submitted_items() {
+    let sum = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let sum = sum.clone();
+        WorkerPool::new(4, 100, move |n: usize| {
+            sum.fetch_add(n, Ordering::SeqCst);
+        })
+    };
+    for n in 1..=100 {
+        pool.submit(n).unwrap();
+    }
+    pool.join();
+    assert_eq!(sum.load(Ordering::SeqCst), 5050);
+}
+
+#[test]
+fn test_rejects_items_when_saturated() {
+    let barrier = Arc::new(Barrier::new(2));
+    let (started_tx, started_rx) = channel();
+    let pool = {
+        let barrier = barrier.clone();
+        WorkerPool::new(1, 1, move |n: usize| {
+            started_tx.send(n).unwrap();
+            barrier.wait();
+        })
+    };
+
+    // The only worker is busy with 1 and 2 fills the queue
+    pool.submit(1).unwrap();
+    assert_eq!(started_rx.recv().unwrap(), 1);
+    pool.submit(2).unwrap();
+    assert_eq!(pool.submit(3), Err(3));
+
+    barrier.wait();
+    assert_eq!(started_rx.recv().unwrap(), 2);
+    barrier.wait();
+    pool.join();
+}
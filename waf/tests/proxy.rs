@@ -0,0 +1,264 @@
+extern crate waf;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use waf::http::{Connection, METHOD_GET, METHOD_POST};
+use waf::proxy::Proxy;
+use waf::response::Response;
+use waf::server::Server;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn upstream(name: &'static str) -> Server {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/health", |_conn: &mut Connection| {
+        Ok(Response::text("ok"))
+    });
+    router.add_handler(METHOD_GET, "/*path", move |conn: &mut Connection| {
+        let header = |key| conn.header(key).unwrap_or("-").to_string();
+        Ok(Response::text(format!(
+            "{} {}?{} host={} for={} proto={} forwarded-host={} secret={}",
+            name,
+            conn.path,
+            conn.query.as_deref().unwrap_or(""),
+            header("Host"),
+            header("X-Forwarded-For"),
+            header("X-Forwarded-Proto"),
+            header("X-Forwarded-Host"),
+            header("X-Secret"),
+        ))
+        .with_header("Keep-Alive", "timeout=5")
+        .with_header("X-Upstream", name))
+    });
+    router.add_handler(METHOD_POST, "/echo", |conn: &mut Connection| {
+        let mut body = Vec::new();
+        conn.read_to_end(&mut body)?;
+        // Streamed back in chunks
+        Ok(Response::streaming(move |writer| {
+            for chunk in body.chunks(1000) {
+                writer.write_all(chunk)?;
+            }
+            Ok(())
+        }))
+    });
+    router.bind("127.0.0.1:0").unwrap()
+}
+
+// An upstream answering one request with response as it is.
+fn raw_upstream(response: &str) -> SocketAddr {
+    let response = response.to_string();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        let _ = reader.get_mut().write_all(response.as_bytes());
+    });
+    addr
+}
+
+fn proxy_client(proxy: Proxy) -> TestClient {
+    let mut router = Router::new();
+    router.add_proxy("/api/*path", proxy);
+    TestClient::new(router)
+}
+
+#[test]
+fn test_proxy_forwards_request() {
+    let a = upstream("a");
+    let addr = a.local_addr().to_string();
+    let client = proxy_client(Proxy::new(&[&addr]).with_strip_prefix("/api"));
+
+    let response = client
+        .get("/api/users?page=2")
+        .with_header("Host", "example.com")
+        .with_header("X-Forwarded-For", "10.0.0.1")
+        .with_header("Connection", "X-Secret")
+        .with_header("X-Secret", "hop")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.text(),
+        format!(
+            "a /users?page=2 host={} for=10.0.0.1 proto=http forwarded-host=example.com secret=-",
+            addr
+        )
+    );
+    assert_eq!(response.header("X-Upstream"), Some("a"));
+    assert_eq!(response.header("Keep-Alive"), None);
+
+    let response = client.head("/api/users").send().unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.body().is_empty());
+    a.shutdown().unwrap();
+}
+
+#[test]
+fn test_proxy_adds_client_address() {
+    let a = upstream("a");
+    let mut router = Router::new();
+    router.add_proxy(
+        "/*path",
+        Proxy::new(&[&a.local_addr().to_string()]).with_preserve_host(true),
+    );
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .write_all(b"GET /x HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(
+        "a /x? host=example.com for=127.0.0.1 proto=http forwarded-host=example.com secret=-"
+    ));
+    server.shutdown().unwrap();
+    a.shutdown().unwrap();
+}
+
+#[test]
+fn test_proxy_streams_bodies() {
+    let a = upstream("a");
+    let client = proxy_client(Proxy::new(&[&a.local_addr().to_string()]).with_strip_prefix("/api"));
+    let body: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+    let response = client
+        .post("/api/echo")
+        .with_body(body.clone())
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.body(), &body[..]);
+
+    let mut chunked = Vec::new();
+    for chunk in body.chunks(30_000) {
+        write!(chunked, "{:x}\r\n", chunk.len()).unwrap();
+        chunked.extend_from_slice(chunk);
+        chunked.extend_from_slice(b"\r\n");
+    }
+    chunked.extend_from_slice(b"0\r\n\r\n");
+    let response = client
+        .post("/api/echo")
+        .with_header("Transfer-Encoding", "chunked")
+        .with_body(chunked)
+        .send()
+        .unwrap();
+    assert_eq!(response.body(), &body[..]);
+    a.shutdown().unwrap();
+}
+
+#[test]
+fn test_proxy_load_balancing_and_failover() {
+    let a = upstream("a");
+    let b = upstream("b");
+    let addrs = [a.local_addr().to_string(), b.local_addr().to_string()];
+    let client = proxy_client(Proxy::new(&[&addrs[0], &addrs[1]]));
+    let upstream_of = |client: &TestClient| {
+        let response = client.get("/api/").send().unwrap();
+        response.header("X-Upstream").unwrap_or("-").to_string()
+    };
+
+    let mut seen: Vec<String> = (0..4).map(|_| upstream_of(&client)).collect();
+    seen.sort();
+    assert_eq!(seen, ["a", "a", "b", "b"]);
+
+    // A refused connection moves on to the next upstream.
+    a.shutdown().unwrap();
+    for _ in 0..4 {
+        assert_eq!(upstream_of(&client), "b");
+    }
+    b.shutdown().unwrap();
+    assert_eq!(client.get("/api/").send().unwrap().status(), 502);
+}
+
+#[test]
+fn test_proxy_health_check() {
+    let a = upstream("a");
+    let b = upstream("b");
+    let addrs = [a.local_addr().to_string(), b.local_addr().to_string()];
+    let client = proxy_client(
+        Proxy::new(&[&addrs[0], &addrs[1]]).with_health_check("/health", Duration::from_millis(50)),
+    );
+
+    b.shutdown().unwrap();
+    thread::sleep(Duration::from_millis(200));
+    for _ in 0..4 {
+        let response = client.get("/api/").send().unwrap();
+        assert_eq!(response.header("X-Upstream"), Some("a"));
+    }
+    a.shutdown().unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.get("/api/").send().unwrap().status(), 503);
+}
+
+#[test]
+fn test_proxy_health_check_set_twice() {
+    let probes = Arc::new(Mutex::new(Vec::new()));
+    let mut router = Router::new();
+    {
+        let probes = probes.clone();
+        router.add_handler(METHOD_GET, "/*path", move |conn: &mut Connection| {
+            probes.lock().unwrap().push(conn.path.clone());
+            Ok(Response::ok())
+        });
+    }
+    let upstream = router.bind("127.0.0.1:0").unwrap();
+    let addr = upstream.local_addr().to_string();
+    let proxy = Proxy::new(&[&addr])
+        .with_health_check("/old", Duration::from_millis(100))
+        .with_health_check("/health", Duration::from_millis(100));
+
+    // One thread checks, with the latest path from its next round
+    thread::sleep(Duration::from_millis(450));
+    drop(proxy);
+    let probes = probes.lock().unwrap().clone();
+    assert!(probes.len() <= 6, "{:?}", probes);
+    assert!(probes.iter().filter(|path| *path == "/old").count() <= 1);
+    assert!(probes.iter().filter(|path| *path == "/health").count() >= 3);
+    upstream.shutdown().unwrap();
+}
+
+#[test]
+fn test_proxy_rejects_unsupported_responses() {
+    let get = |response: &str| {
+        let addr = raw_upstream(response).to_string();
+        proxy_client(Proxy::new(&[&addr]))
+            .get("/api/")
+            .send()
+            .unwrap()
+    };
+
+    let response = get("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "ok");
+
+    // Codings other than chunked can't be delimited
+    for coding in &["gzip, chunked", "gzip"] {
+        let response = get(&format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: {}\r\n\r\nbody",
+            coding
+        ));
+        assert_eq!(response.status(), 502, "{}", coding);
+    }
+
+    // Upgrades aren't forwarded, so switching protocols is an upstream error, not an interim
+    // response
+    let response = get("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    assert_eq!(response.status(), 502);
+
+    let response =
+        get("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text(), "ok");
+}
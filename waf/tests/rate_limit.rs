@@ -0,0 +1,52 @@
+extern crate waf;
+
+use std::thread;
+use std::time::Duration;
+
+use waf::http::{Connection, METHOD_GET};
+use waf::rate_limit::RateLimit;
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn client(limit: RateLimit) -> TestClient {
+    let mut router = Router::new();
+    router.add_middleware(limit.with_key(|conn| conn.header("X-Client").map(str::to_string)));
+    router.add_handler(METHOD_GET, "/", |_conn: &mut Connection| {
+        Ok(Response::text("Hello"))
+    });
+    TestClient::new(router)
+}
+
+#[test]
+fn test_rate_limit_per_client() {
+    let client = client(RateLimit::new(2, Duration::from_secs(60)));
+    let get = |key: &str| client.get("/").with_header("X-Client", key).send().unwrap();
+
+    assert_eq!(get("a").status(), 200);
+    assert_eq!(get("a").status(), 200);
+    let response = get("a");
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.header("Retry-After"), Some("30"));
+
+    assert_eq!(get("b").status(), 200);
+    // Requests without a key aren't limited.
+    for _ in 0..5 {
+        assert_eq!(client.get("/").send().unwrap().status(), 200);
+    }
+}
+
+#[test]
+fn test_rate_limit_refills() {
+    let client = client(RateLimit::new(10, Duration::from_secs(1)).with_burst(1));
+    let get = || client.get("/").with_header("X-Client", "a").send().unwrap();
+
+    assert_eq!(get().status(), 200);
+    let response = get();
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.header("Retry-After"), Some("1"));
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(get().status(), 200);
+    assert_eq!(get().status(), 429);
+}
@@ -0,0 +1,150 @@
+extern crate waf;
+
+use std::io::{Cursor, Result, Write};
+use std::sync::{Arc, Mutex};
+
+use waf::http::Connection;
+use waf::response::Response;
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Sends response to request and returns the bytes written, with the Date header left out.
+fn send(request: &str, response: Response) -> String {
+    let output = Output::default();
+    let mut conn = Connection::from_io(Cursor::new(request.as_bytes().to_vec()), output.clone());
+    assert!(conn.next_request().unwrap());
+    conn.send(response).unwrap();
+    drop(conn);
+    let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    written
+        .split_inclusive("\r\n")
+        .filter(|line| !line.starts_with("Date: "))
+        .collect()
+}
+
+fn get(response: Response) -> String {
+    send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", response)
+}
+
+#[test]
+fn test_content_length() {
+    assert_eq!(
+        get(Response::text("hello")),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: 5\r\n\r\nhello"
+    );
+    assert_eq!(
+        get(Response::new(404)),
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+    );
+
+    // HEAD gets the length of the body it doesn't get
+    assert_eq!(
+        send(
+            "HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            Response::text("hello")
+        ),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: 5\r\n\r\n"
+    );
+
+    // Responses that can't have a body get no length at all
+    for status in &[204, 304] {
+        let written = get(Response::new(*status));
+        assert!(!written.contains("Content-Length"), "{}", written);
+        assert!(!written.contains("Transfer-Encoding"), "{}", written);
+    }
+}
+
+#[test]
+fn test_chunked() {
+    let written = get(Response::stream(Cursor::new(b"streamed".to_vec())));
+    assert_eq!(
+        written,
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"
+    );
+
+    let written = get(Response::streaming(|writer| {
+        writer.write_all(b"one ")?;
+        writer.write_all(b"two")
+    }));
+    assert!(written.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(written.ends_with("\r\n\r\n7\r\none two\r\n0\r\n\r\n"));
+
+    // A length given by the handler is used instead
+    let written =
+        get(Response::stream(Cursor::new(b"streamed".to_vec())).with_header("Content-Length", "8"));
+    assert!(!written.contains("Transfer-Encoding"));
+    assert!(written.ends_with("\r\nContent-Length: 8\r\n\r\nstreamed"));
+
+    // HTTP/1.0 has no chunked coding, so the end of the body is the end of the connection
+    let written = send(
+        "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        Response::stream(Cursor::new(b"streamed".to_vec())),
+    );
+    assert_eq!(
+        written,
+        "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nstreamed"
+    );
+}
+
+#[test]
+fn test_redirect() {
+    let response = Response::redirect(302, "/login?next=%2F");
+    assert_eq!(response.status(), 302);
+    assert_eq!(response.reason(), "Found");
+    assert_eq!(
+        get(response),
+        "HTTP/1.1 302 Found\r\nLocation: /login?next=%2F\r\nContent-Length: 0\r\n\r\n"
+    );
+
+    let written = get(Response::redirect(308, "https://example.com/"));
+    assert!(written.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+    assert!(written.contains("\r\nLocation: https://example.com/\r\n"));
+}
+
+#[test]
+fn test_headers_replace_and_append() {
+    let mut response = Response::ok()
+        .with_header("X-Single", "first")
+        .with_header("x-single", "second");
+    response.append_header("Set-Cookie", "a=1");
+    response.append_header("Set-Cookie", "b=2");
+    assert_eq!(response.header("X-Single"), Some("second"));
+    assert_eq!(response.headers().get_all("X-Single").count(), 1);
+
+    let written = get(response);
+    assert!(written.contains("\r\nx-single: second\r\n"), "{}", written);
+    assert!(!written.contains("first"));
+    assert!(written.contains("\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n"));
+
+    let mut response = Response::ok();
+    response.append_header("Set-Cookie", "a=1");
+    response.append_header("Set-Cookie", "b=2");
+    response.set_header("Set-Cookie", "c=3");
+    assert_eq!(
+        response.headers().get_all("Set-Cookie").collect::<Vec<_>>(),
+        ["c=3"]
+    );
+    response.remove_header("SET-COOKIE");
+    assert!(!response.headers().contains("Set-Cookie"));
+
+    let mut response = Response::ok();
+    response.add_vary("Accept-Encoding");
+    response.add_vary("Origin");
+    response.add_vary("accept-encoding");
+    assert_eq!(
+        response.headers().get_all("Vary").collect::<Vec<_>>(),
+        ["Accept-Encoding", "Origin"]
+    );
+}
@@ -0,0 +1,90 @@
+extern crate waf;
+
+use waf::route::Tree;
+
+fn tree() -> Tree<&'static str> {
+    let mut tree = Tree::new();
+    tree.insert("/", "root");
+    tree.insert("/users", "users");
+    tree.insert("/users/new", "new user");
+    tree.insert("/users/:id<uint>", "user by id");
+    tree.insert("/users/:name", "user by name");
+    tree.insert("/users/:name/posts/:post", "post");
+    tree.insert("/static/*path", "static");
+    tree
+}
+
+#[test]
+fn test_static_route() {
+    let tree = tree();
+    let (value, params) = tree.find("/users").unwrap();
+    assert_eq!(*value, "users");
+    assert!(params.is_empty());
+    assert_eq!(*tree.find("/").unwrap().0, "root");
+}
+
+#[test]
+fn test_static_route_has_priority_over_param() {
+    assert_eq!(*tree().find("/users/new").unwrap().0, "new user");
+}
+
+#[test]
+fn test_typed_param_has_priority_over_untyped_param() {
+    let tree = tree();
+    let (value, params) = tree.find("/users/42").unwrap();
+    assert_eq!(*value, "user by id");
+    assert_eq!(params.get("id"), Some("42"));
+    assert_eq!(params.parse::<u32>("id").unwrap(), 42);
+
+    let (value, params) = tree.find("/users/alice").unwrap();
+    assert_eq!(*value, "user by name");
+    assert_eq!(params.get("name"), Some("alice"));
+    assert!(params.parse::<u32>("name").is_err());
+}
+
+#[test]
+fn test_backtracks_to_less_specific_param() {
+    let tree = tree();
+    let (value, params) = tree.find("/users/42/posts/7").unwrap();
+    assert_eq!(*value, "post");
+    assert_eq!(params.get("name"), Some("42"));
+    assert_eq!(params.get("post"), Some("7"));
+    assert_eq!(params.get("id"), None);
+}
+
+#[test]
+fn test_param_is_percent_decoded() {
+    let tree = tree();
+    let (_, params) = tree.find("/users/John%20Doe").unwrap();
+    assert_eq!(params.get("name"), Some("John Doe"));
+}
+
+#[test]
+fn test_wildcard_captures_rest_of_path() {
+    let tree = tree();
+    let (value, params) = tree.find("/static/css/site.css").unwrap();
+    assert_eq!(*value, "static");
+    assert_eq!(params.get("path"), Some("css/site.css"));
+    assert_eq!(tree.find("/static").unwrap().1.get("path"), Some(""));
+}
+
+#[test]
+fn test_no_match() {
+    let tree = tree();
+    assert!(tree.find("/posts").is_none());
+    assert!(tree.find("/users/42/comments").is_none());
+}
+
+#[test]
+#[should_panic]
+fn test_conflicting_param_names_panic() {
+    let mut tree = tree();
+    tree.insert("/users/:user", "conflict");
+}
+
+#[test]
+#[should_panic]
+fn test_wildcard_must_be_last() {
+    let mut tree = Tree::new();
+    tree.insert("/static/*path/more", "invalid");
+}
@@ -0,0 +1,301 @@
+extern crate waf;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use waf::http::{Connection, METHOD_GET};
+use waf::response::Response;
+use waf::Router;
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/", |_conn: &mut Connection| {
+        Ok(Response::text("Hello"))
+    });
+    router.add_handler(METHOD_GET, "/sleep/:ms<uint>", |conn: &mut Connection| {
+        thread::sleep(Duration::from_millis(conn.params.parse("ms")?));
+        Ok(Response::text("Good morning"))
+    });
+    router
+}
+
+fn request(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// Reads one response with a Content-Length from a kept-alive connection.
+fn read_response(reader: &mut BufReader<TcpStream>) -> String {
+    let mut response = String::new();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        assert!(
+            reader.read_line(&mut line).unwrap() > 0,
+            "connection closed"
+        );
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            length = value.trim().parse().unwrap();
+        }
+        response.push_str(&line);
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    response + &String::from_utf8(body).unwrap()
+}
+
+fn assert_closed(reader: &mut BufReader<TcpStream>) {
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "{}", String::from_utf8_lossy(&rest));
+}
+
+fn connect(addr: SocketAddr) -> BufReader<TcpStream> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    BufReader::new(stream)
+}
+
+#[test]
+fn test_bind_to_port_0_reports_local_addr() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+    assert_ne!(addr.port(), 0);
+
+    let response = request(addr, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello"));
+
+    server.shutdown().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_stop_from_handle() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let handle = server.handle();
+    assert!(!handle.is_stopped());
+
+    let waiter = thread::spawn(move || server.wait());
+    handle.stop();
+    waiter.join().unwrap().unwrap();
+    assert!(handle.is_stopped());
+}
+
+#[test]
+fn test_shutdown_drains_in_flight_requests() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+
+    let client = thread::spawn(move || request(addr, "/sleep/300"));
+    thread::sleep(Duration::from_millis(100));
+    server.shutdown().unwrap();
+
+    let response = client.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("Good morning"));
+}
+
+#[test]
+fn test_shutdown_closes_idle_keep_alive_connections() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(stream.read(&mut buf).unwrap() > 0);
+
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(stream.read(&mut buf).unwrap_or(0), 0);
+}
+
+#[test]
+fn test_shutdown_closes_connections_without_a_request() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    // Until a worker waits for the request
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let mut buf = [0; 1024];
+    assert_eq!(stream.read(&mut buf).unwrap_or(0), 0);
+}
+
+#[test]
+fn test_shutdown_timeout() {
+    let mut router = router();
+    router.set_shutdown_timeout(Duration::from_millis(100));
+    let server = router.bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /sleep/1000 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map(|_| response)
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let e = server.shutdown().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(client.join().unwrap().map_or(true, |r| r.is_empty()));
+    // The accept thread is still joined, so the handler got to finish
+    assert!(start.elapsed() >= Duration::from_millis(800));
+}
+
+#[test]
+fn test_slow_request_head_times_out() {
+    let mut router = router();
+    router.set_header_timeout(Some(Duration::from_millis(200)));
+    let server = router.bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    stream.write_all(b"Host: localhost\r\n").unwrap();
+
+    let started = Instant::now();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_max_connections() {
+    let mut router = router();
+    router.set_max_connections(1);
+    let server = router.bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+
+    let mut first = TcpStream::connect(addr).unwrap();
+    first
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(first.read(&mut buf).unwrap() > 0);
+
+    // Rejected as soon as it's accepted, without reading a request.
+    let mut second = TcpStream::connect(addr).unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    let response = request(addr, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_pipelining() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let mut conn = connect(server.local_addr());
+    conn.get_mut()
+        .write_all(
+            b"GET /sleep/50 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /sleep/0 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+
+    // Answered in order, the slow one first
+    assert!(read_response(&mut conn).ends_with("\r\n\r\nGood morning"));
+    assert!(read_response(&mut conn).ends_with("\r\n\r\nHello"));
+    assert!(read_response(&mut conn).ends_with("\r\n\r\nGood morning"));
+
+    // The connection is still open for more
+    conn.get_mut()
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut conn).ends_with("Hello"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_connection_close() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+    let mut conn = connect(server.local_addr());
+    conn.get_mut()
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+    let response = read_response(&mut conn);
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    // The request after it is never answered
+    assert_closed(&mut conn);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_http_1_0_keep_alive() {
+    let server = router().bind("127.0.0.1:0").unwrap();
+
+    // HTTP/1.0 closes after each response unless the client asks for keep-alive
+    let mut conn = connect(server.local_addr());
+    conn.get_mut().write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let response = read_response(&mut conn);
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    assert_closed(&mut conn);
+
+    let mut conn = connect(server.local_addr());
+    for _ in 0..2 {
+        conn.get_mut()
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut conn);
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(response.ends_with("Hello"));
+    }
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_max_requests_per_connection() {
+    let mut router = router();
+    router.set_max_requests_per_connection(2);
+    let server = router.bind("127.0.0.1:0").unwrap();
+    let mut conn = connect(server.local_addr());
+    conn.get_mut()
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+
+    let first = read_response(&mut conn);
+    assert!(!first.contains("Connection: close"));
+    // The last allowed request tells the client the connection ends
+    let second = read_response(&mut conn);
+    assert!(second.contains("\r\nConnection: close\r\n"));
+    assert_closed(&mut conn);
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,154 @@
+extern crate waf;
+
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use waf::http::{Connection, METHOD_GET, METHOD_POST};
+use waf::response::Response;
+use waf::session::{FileStore, MemoryStore, SessionData, SessionStore, Sessions};
+use waf::testing::{TestClient, TestResponse};
+use waf::Router;
+
+fn client<S: SessionStore>(store: S) -> TestClient {
+    let mut router = Router::new();
+    router.add_middleware(Sessions::new(store).with_cookie_name("sid"));
+    router.add_handler(METHOD_POST, "/login", |conn: &mut Connection| {
+        let session = conn.session().unwrap();
+        session.set("user", "alice");
+        session.renew();
+        Ok(Response::new(204))
+    });
+    router.add_handler(METHOD_GET, "/me", |conn: &mut Connection| {
+        let user = conn.session().unwrap().get("user").map(str::to_string);
+        Ok(Response::text(
+            user.unwrap_or_else(|| "anonymous".to_string()),
+        ))
+    });
+    router.add_handler(METHOD_POST, "/logout", |conn: &mut Connection| {
+        conn.session().unwrap().destroy();
+        Ok(Response::new(204))
+    });
+    TestClient::new(router)
+}
+
+fn session_cookie(response: &TestResponse) -> String {
+    let set_cookie = response.header("Set-Cookie").unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+fn login_logout(client: &TestClient) {
+    let response = client.get("/me").send().unwrap();
+    assert_eq!(response.text(), "anonymous");
+    assert_eq!(response.header("Set-Cookie"), None);
+
+    let response = client.post("/login").send().unwrap();
+    let set_cookie = response.header("Set-Cookie").unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("SameSite=Lax"));
+    let cookie = session_cookie(&response);
+
+    let response = client
+        .get("/me")
+        .with_header("Cookie", &cookie)
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "alice");
+
+    // Logging in again moves the session to a new ID.
+    let response = client
+        .post("/login")
+        .with_header("Cookie", &cookie)
+        .send()
+        .unwrap();
+    let renewed = session_cookie(&response);
+    assert_ne!(renewed, cookie);
+    let response = client
+        .get("/me")
+        .with_header("Cookie", &cookie)
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "anonymous");
+
+    let response = client
+        .post("/logout")
+        .with_header("Cookie", &renewed)
+        .send()
+        .unwrap();
+    assert!(response.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+    let response = client
+        .get("/me")
+        .with_header("Cookie", &renewed)
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "anonymous");
+}
+
+#[test]
+fn test_memory_store_sessions() {
+    login_logout(&client(MemoryStore::new()));
+
+    let response = client(MemoryStore::new())
+        .get("/me")
+        .with_header("Cookie", "sid=../../etc/passwd")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "anonymous");
+}
+
+#[test]
+fn test_file_store_sessions() {
+    let dir = env::temp_dir().join(format!("waf-sessions-{}", std::process::id()));
+    login_logout(&client(FileStore::new(&dir).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_store_expiry() {
+    let dir = env::temp_dir().join(format!("waf-session-expiry-{}", std::process::id()));
+    let stores: Vec<Box<dyn SessionStore>> = vec![
+        Box::new(MemoryStore::new()),
+        Box::new(FileStore::new(&dir).unwrap()),
+    ];
+    let id = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    let mut data = SessionData::new();
+    data.insert("note".to_string(), "tab\there\nand newline\\".to_string());
+    for store in &stores {
+        store.save(id, &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load(id).unwrap(), Some(data.clone()));
+        store.save(id, &data, Duration::from_secs(0)).unwrap();
+        assert_eq!(store.load(id).unwrap(), None);
+        store.remove(id).unwrap();
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_store_concurrent_saves() {
+    let dir = env::temp_dir().join(format!("waf-session-saves-{}", std::process::id()));
+    let store = Arc::new(FileStore::new(&dir).unwrap());
+    let id = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let store = store.clone();
+            thread::spawn(move || {
+                let mut data = SessionData::new();
+                data.insert("writer".to_string(), i.to_string());
+                for _ in 0..50 {
+                    store.save(id, &data, Duration::from_secs(60)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // One of the saves, whole, and no temporary files left over
+    let data = store.load(id).unwrap().unwrap();
+    assert!(data["writer"].parse::<u32>().unwrap() < 8);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,142 @@
+extern crate waf;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use waf::http::{Connection, METHOD_GET};
+use waf::response::Response;
+use waf::sse::Event;
+use waf::testing::TestClient;
+use waf::Router;
+
+#[test]
+fn test_event_format() {
+    assert_eq!(Event::new("hello").to_string(), "data: hello\n\n");
+    let event = Event::new("line 1\nline 2\r\nline 3")
+        .with_event("update")
+        .with_id("7")
+        .with_retry(Duration::from_secs(3));
+    assert_eq!(
+        event.to_string(),
+        "event: update\nid: 7\nretry: 3000\ndata: line 1\ndata: line 2\ndata: line 3\n\n"
+    );
+    assert_eq!(Event::default().with_id("8").to_string(), "id: 8\n\n");
+}
+
+#[test]
+#[should_panic]
+fn test_event_id_with_newline() {
+    Event::new("").with_id("1\n2");
+}
+
+#[test]
+fn test_event_stream_resumes_after_last_event_id() {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/events", |conn: &mut Connection| {
+        let start = match conn.last_event_id() {
+            Some(id) => id.parse::<u32>().unwrap() + 1,
+            None => 1,
+        };
+        Ok(Response::event_stream(move |events| {
+            events.comment("counting")?;
+            for i in start..=3 {
+                events.send(&Event::new(&i.to_string()).with_id(&i.to_string()))?;
+            }
+            Ok(())
+        }))
+    });
+    let client = TestClient::new(router);
+
+    let response = client.get("/events").send().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+    assert_eq!(
+        response.header("Cache-Control"),
+        Some("no-cache, no-transform")
+    );
+    assert_eq!(
+        response.text(),
+        ": counting\n\nid: 1\ndata: 1\n\nid: 2\ndata: 2\n\nid: 3\ndata: 3\n\n"
+    );
+
+    let response = client
+        .get("/events")
+        .with_header("Last-Event-ID", "2")
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), ": counting\n\nid: 3\ndata: 3\n\n");
+}
+
+#[test]
+fn test_event_channel_heartbeat_and_disconnect() {
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/events", move |conn: &mut Connection| {
+        let (tx, rx) = mpsc::channel();
+        let done = done_tx.clone();
+        thread::spawn(move || {
+            tx.send(Event::new("first")).unwrap();
+            thread::sleep(Duration::from_millis(150));
+            // Sending fails once the stream has noticed the client is gone.
+            while tx.send(Event::new("more")).is_ok() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            done.send(()).unwrap();
+        });
+        Ok(Response::event_channel(conn, rx, Duration::from_millis(50)))
+    });
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut lines = Vec::new();
+    while !lines.iter().any(|line: &String| line.starts_with(":")) {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        lines.push(line);
+    }
+    assert!(lines.iter().any(|line| line.starts_with("data: first")));
+    drop(reader);
+
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_event_channel_ends_on_shutdown() {
+    let (senders_tx, senders_rx) = mpsc::channel();
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/events", move |conn: &mut Connection| {
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::new("first")).unwrap();
+        // Kept alive, so that only the shutdown can end the stream
+        senders_tx.send(tx).unwrap();
+        Ok(Response::event_channel(conn, rx, Duration::from_secs(60)))
+    });
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while !line.starts_with("data: first") {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+    }
+
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert!(rest.ends_with("0\r\n\r\n"));
+    drop(senders_rx);
+}
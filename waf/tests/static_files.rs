@@ -0,0 +1,198 @@
+extern crate waf;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+
+use waf::http::METHOD_GET;
+use waf::server::Server;
+use waf::static_files::StaticFiles;
+use waf::Router;
+
+fn setup(name: &str, listing: bool) -> (Server, PathBuf) {
+    let root = std::env::temp_dir().join(format!("waf-static-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("css")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("hello.txt"), "Hello, World!").unwrap();
+    fs::write(root.join("css/site.css"), "body {}").unwrap();
+    fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+
+    let mut router = Router::new();
+    router.add_handler(
+        METHOD_GET,
+        "/assets/*path",
+        StaticFiles::new(&root).with_listing(listing),
+    );
+    (router.bind("127.0.0.1:0").unwrap(), root)
+}
+
+fn get(addr: SocketAddr, path: &str, headers: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+        path, headers
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let i = response.find("\r\n\r\n").unwrap();
+    (response[..i].to_string(), response[i + 4..].to_string())
+}
+
+fn header<'a>(head: &'a str, key: &str) -> Option<&'a str> {
+    head.lines()
+        .filter_map(|line| line.split_once(": "))
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+#[test]
+fn test_serves_file_with_mime_type_and_validators() {
+    let (server, root) = setup("file", false);
+    let (head, body) = get(server.local_addr(), "/assets/css/site.css", "");
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(
+        header(&head, "Content-Type"),
+        Some("text/css; charset=UTF-8")
+    );
+    assert_eq!(header(&head, "Content-Length"), Some("7"));
+    assert!(header(&head, "ETag").is_some());
+    assert!(header(&head, "Last-Modified").unwrap().ends_with(" GMT"));
+    assert_eq!(body, "body {}");
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_conditional_requests() {
+    let (server, root) = setup("conditional", false);
+    let addr = server.local_addr();
+    let (head, _) = get(addr, "/assets/hello.txt", "");
+    let etag = header(&head, "ETag").unwrap().to_string();
+    let last_modified = header(&head, "Last-Modified").unwrap().to_string();
+
+    let (head, body) = get(
+        addr,
+        "/assets/hello.txt",
+        &format!("If-None-Match: {}\r\n", etag),
+    );
+    assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+    assert_eq!(body, "");
+
+    let (head, _) = get(
+        addr,
+        "/assets/hello.txt",
+        &format!("If-Modified-Since: {}\r\n", last_modified),
+    );
+    assert!(head.starts_with("HTTP/1.1 304 Not Modified"));
+
+    let (head, _) = get(addr, "/assets/hello.txt", "If-None-Match: \"other\"\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_range_requests() {
+    let (server, root) = setup("range", false);
+    let addr = server.local_addr();
+
+    let (head, body) = get(addr, "/assets/hello.txt", "Range: bytes=0-4\r\n");
+    assert!(head.starts_with("HTTP/1.1 206 Partial Content"));
+    assert_eq!(header(&head, "Content-Range"), Some("bytes 0-4/13"));
+    assert_eq!(body, "Hello");
+
+    let (_, body) = get(addr, "/assets/hello.txt", "Range: bytes=7-\r\n");
+    assert_eq!(body, "World!");
+
+    let (_, body) = get(addr, "/assets/hello.txt", "Range: bytes=-6\r\n");
+    assert_eq!(body, "World!");
+
+    let (head, _) = get(addr, "/assets/hello.txt", "Range: bytes=100-\r\n");
+    assert!(head.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+    assert_eq!(header(&head, "Content-Range"), Some("bytes */13"));
+
+    let (head, body) = get(
+        addr,
+        "/assets/hello.txt",
+        "Range: bytes=0-4\r\nIf-Range: \"stale\"\r\n",
+    );
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(body, "Hello, World!");
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_rejects_path_traversal() {
+    let (server, root) = setup("traversal", false);
+    fs::write(root.with_extension("secret"), "secret").unwrap();
+    let addr = server.local_addr();
+
+    let (head, _) = get(addr, "/assets/../hello.txt", "");
+    assert!(head.starts_with("HTTP/1.1 404"));
+    let (head, _) = get(addr, "/assets/%2e%2e/waf-static-traversal.secret", "");
+    assert!(head.starts_with("HTTP/1.1 404"));
+    let (head, _) = get(addr, "/assets/css/%2e%2e/%2e%2e/x.secret", "");
+    assert!(head.starts_with("HTTP/1.1 404"));
+    let (head, _) = get(addr, "/assets/missing.txt", "");
+    assert!(head.starts_with("HTTP/1.1 404"));
+    server.shutdown().unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_file(root.with_extension("secret")).unwrap();
+}
+
+#[test]
+fn test_directories() {
+    let (server, root) = setup("listing", true);
+    let addr = server.local_addr();
+
+    let (head, _) = get(addr, "/assets/docs", "");
+    assert!(head.starts_with("HTTP/1.1 301"));
+    assert_eq!(header(&head, "Location"), Some("/assets/docs/"));
+
+    let (_, body) = get(addr, "/assets/docs/", "");
+    assert_eq!(body, "<h1>Docs</h1>");
+
+    let (head, body) = get(addr, "/assets/", "");
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert!(body.contains("<a href=\"css/\">css/</a>"));
+    assert!(body.contains("<a href=\"hello.txt\">hello.txt</a>"));
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_directory_listing_links() {
+    let (server, root) = setup("links", true);
+    let addr = server.local_addr();
+    fs::write(root.join("50% <off> #1?.txt"), "sale").unwrap();
+    fs::create_dir(root.join("a:b")).unwrap();
+
+    // Links are percent-encoded, names only escaped for HTML
+    let (_, body) = get(addr, "/assets/", "");
+    assert!(
+        body.contains("<a href=\"50%25%20%3Coff%3E%20%231%3F.txt\">50% &lt;off&gt; #1?.txt</a>"),
+        "{}",
+        body
+    );
+    assert!(body.contains("<a href=\"a%3Ab/\">a:b/</a>"), "{}", body);
+
+    let (head, body) = get(addr, "/assets/50%25%20%3Coff%3E%20%231%3F.txt", "");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+    assert_eq!(body, "sale");
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_directory_listing_is_disabled_by_default() {
+    let (server, root) = setup("nolisting", false);
+    let (head, _) = get(server.local_addr(), "/assets/css/", "");
+    assert!(head.starts_with("HTTP/1.1 404"));
+    server.shutdown().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,161 @@
+extern crate waf;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+use waf::template::Templates;
+
+fn template_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("waf-templates-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, source) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_variables_and_escaping() {
+    let dir = template_dir(
+        "variables",
+        &[(
+            "page.html",
+            "<p title=\"{{ user.name }}\">{{ user.name }}</p>{{ html | raw }} {{ user.tags.1 | upper }} \
+             {{ user.tags | length }} {{ missing }}{# comment #} {{ count }}",
+        )],
+    );
+    let templates = Templates::new(&dir);
+    let context = json!({
+        "user": { "name": "<Tom & \"Jerry\">", "tags": ["a", "b"] },
+        "html": "<b>bold</b>",
+        "count": 3,
+    });
+    assert_eq!(
+        templates.render("page.html", &context).unwrap(),
+        "<p title=\"&lt;Tom &amp; &quot;Jerry&quot;&gt;\">&lt;Tom &amp; &quot;Jerry&quot;&gt;</p>\
+         <b>bold</b> B 2  3"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_conditionals_and_loops() {
+    let dir = template_dir(
+        "control",
+        &[
+            (
+                "list.html",
+                "{% for item in items %}{{ loop.index }}.{{ item.name }}\
+                 {% if item.done %}+{% elif item.name == \"b\" and not item.done %}?{% else %}-{% endif %}\
+                 {% if not loop.last %},{% endif %}{% else %}empty{% endfor %}",
+            ),
+            ("map.html", "{% for key, value in map %}{{ key }}={{ value }};{% endfor %}"),
+        ],
+    );
+    let templates = Templates::new(&dir);
+    let items = json!({ "items": [
+        { "name": "a", "done": true },
+        { "name": "b", "done": false },
+        { "name": "c" },
+    ]});
+    assert_eq!(
+        templates.render("list.html", &items).unwrap(),
+        "1.a+,2.b?,3.c-"
+    );
+    assert_eq!(
+        templates
+            .render("list.html", &json!({ "items": [] }))
+            .unwrap(),
+        "empty"
+    );
+    assert_eq!(
+        templates
+            .render("map.html", &json!({ "map": { "x": 1, "y": 2 } }))
+            .unwrap(),
+        "x=1;y=2;"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_layouts_and_includes() {
+    let dir = template_dir(
+        "layouts",
+        &[
+            (
+                "layout.html",
+                "<title>{% block title %}Site{% endblock %}</title>{% include \"nav.html\" %}\
+                 <main>{% block content %}{% endblock %}</main>",
+            ),
+            ("nav.html", "<nav>{{ user }}</nav>"),
+            (
+                "pages/home.html",
+                "{% extends \"layout.html\" %}ignored{% block content %}Hello {{ user }}{% endblock %}",
+            ),
+            ("loop.html", "{% include \"loop.html\" %}"),
+        ],
+    );
+    let templates = Templates::new(&dir);
+    assert_eq!(
+        templates
+            .render("pages/home.html", &json!({ "user": "alice" }))
+            .unwrap(),
+        "<title>Site</title><nav>alice</nav><main>Hello alice</main>"
+    );
+
+    let e = templates.render("loop.html", &json!({})).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let e = templates.render("../secret.html", &json!({})).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = templates.render("missing.html", &json!({})).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_syntax_errors() {
+    let dir = template_dir(
+        "errors",
+        &[
+            ("unclosed.html", "line 1\n{% if x %}\nyes"),
+            ("unknown.html", "\n\n{% frobnicate %}"),
+            ("filter.html", "{{ x | shout }}"),
+        ],
+    );
+    let templates = Templates::new(&dir);
+    let error = |name| templates.render(name, &json!({})).unwrap_err().to_string();
+    assert_eq!(error("unclosed.html"), "unclosed.html: missing {% endif %}");
+    assert_eq!(
+        error("unknown.html"),
+        "unknown.html:3: unexpected {% frobnicate %}"
+    );
+    assert_eq!(error("filter.html"), "filter.html:1: unknown filter shout");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_reload() {
+    let dir = template_dir("reload", &[("page.html", "old")]);
+    let path = dir.join("page.html");
+    let cached = Templates::new(&dir).with_reload(false);
+    let reloaded = Templates::new(&dir).with_reload(true);
+    assert_eq!(cached.render("page.html", &()).unwrap(), "old");
+    assert_eq!(reloaded.render("page.html", &()).unwrap(), "old");
+
+    fs::write(&path, "new").unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(cached.render("page.html", &()).unwrap(), "old");
+    assert_eq!(reloaded.render("page.html", &()).unwrap(), "new");
+    fs::remove_dir_all(dir).unwrap();
+}
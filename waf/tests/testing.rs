@@ -0,0 +1,106 @@
+extern crate waf;
+
+use std::io::Read;
+
+use waf::http::{Connection, METHOD_GET, METHOD_POST};
+use waf::middleware::Next;
+use waf::response::Response;
+use waf::testing::TestClient;
+use waf::Router;
+
+fn client() -> TestClient {
+    let mut router = Router::new();
+    router.add_middleware(|conn: &mut Connection, next: Next| {
+        let mut response = next.run(conn)?;
+        response.set_header("X-Middleware", "yes");
+        Ok(response)
+    });
+    router.add_handler(METHOD_GET, "/users/:id<uint>", |conn: &mut Connection| {
+        let id: u64 = conn.params.parse("id")?;
+        Ok(Response::json(format!("{{\"id\":{}}}", id)))
+    });
+    router.add_handler(METHOD_POST, "/echo", |conn: &mut Connection| {
+        let mut body = String::new();
+        conn.read_to_string(&mut body)?;
+        let content_type = conn.header("Content-Type").unwrap_or("").to_string();
+        Ok(Response::text(body).with_header("X-Content-Type", &content_type))
+    });
+    router.add_handler(METHOD_GET, "/count", |_conn: &mut Connection| {
+        Ok(Response::streaming(|writer| {
+            for i in 1..=3 {
+                writeln!(writer, "{}", i)?;
+            }
+            Ok(())
+        }))
+    });
+    TestClient::new(router)
+}
+
+#[test]
+fn test_get() {
+    let client = client();
+    let response = client.get("/users/42").send().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.reason(), "OK");
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/json; charset=UTF-8")
+    );
+    assert_eq!(response.header("X-Middleware"), Some("yes"));
+    assert_eq!(response.text(), "{\"id\":42}");
+}
+
+#[test]
+fn test_post_body() {
+    let client = client();
+    let response = client
+        .post("/echo")
+        .with_header("Content-Type", "text/plain")
+        .with_body("hello")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("X-Content-Type"), Some("text/plain"));
+    assert_eq!(response.body(), b"hello");
+}
+
+#[test]
+fn test_chunked_response() {
+    let response = client().get("/count").send().unwrap();
+    assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(response.text(), "1\n2\n3\n");
+}
+
+#[test]
+fn test_errors() {
+    let client = client();
+    assert_eq!(client.get("/users/abc").send().unwrap().status(), 404);
+    assert_eq!(client.get("/missing").send().unwrap().status(), 404);
+    assert_eq!(client.post("/users/1").send().unwrap().status(), 405);
+
+    let response = client.send_raw(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn test_head_and_options() {
+    let client = client();
+    let response = client.head("/users/42").send().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.header("Content-Length"), Some("9"));
+    assert_eq!(response.header("X-Middleware"), Some("yes"));
+    assert!(response.body().is_empty());
+
+    let response = client.request("OPTIONS", "/users/42").send().unwrap();
+    assert_eq!(response.status(), 204);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(
+        client
+            .request("OPTIONS", "/missing")
+            .send()
+            .unwrap()
+            .status(),
+        404
+    );
+}
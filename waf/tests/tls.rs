@@ -0,0 +1,194 @@
+#![cfg(feature = "tls")]
+extern crate waf;
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use waf::http::{Connection, METHOD_GET};
+use waf::response::Response;
+use waf::tls::{Certificate, TlsConfig};
+use waf::Router;
+
+struct SelfSigned {
+    certificate: Certificate,
+    der: CertificateDer<'static>,
+}
+
+fn self_signed(name: &str) -> SelfSigned {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let certificate = Certificate::from_pem(
+        generated.cert.pem().as_bytes(),
+        generated.key_pair.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    SelfSigned {
+        certificate,
+        der: generated.cert.der().clone(),
+    }
+}
+
+fn connect(
+    addr: SocketAddr,
+    name: &str,
+    trusted: &CertificateDer<'static>,
+) -> std::io::Result<StreamOwned<ClientConnection, TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots.add(trusted.clone()).unwrap();
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let session = ClientConnection::new(
+        Arc::new(config),
+        ServerName::try_from(name.to_string()).unwrap(),
+    )
+    .unwrap();
+
+    Ok(StreamOwned::new(session, TcpStream::connect(addr)?))
+}
+
+fn get(addr: SocketAddr, name: &str, trusted: &CertificateDer<'static>) -> std::io::Result<String> {
+    let mut stream = connect(addr, name, trusted)?;
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        name
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router.add_handler(METHOD_GET, "/", |_conn: &mut Connection| {
+        Ok(Response::text("secure"))
+    });
+    router
+}
+
+#[test]
+fn test_https() {
+    let localhost = self_signed("localhost");
+    let mut router = router();
+    router.set_tls(TlsConfig::new(localhost.certificate.clone()));
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let response = get(server.local_addr(), "localhost", &localhost.der).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nsecure"));
+
+    // Plain HTTP doesn't get an answer
+    let mut plain = TcpStream::connect(server.local_addr()).unwrap();
+    plain
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/1.1"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_sni() {
+    let default = self_signed("default.test");
+    let a = self_signed("a.test");
+    let wildcard = self_signed("*.b.test");
+    let mut router = router();
+    router.set_tls(
+        TlsConfig::new(default.certificate.clone())
+            .with_server_name("a.test", a.certificate.clone())
+            .with_server_name("*.b.test", wildcard.certificate.clone()),
+    );
+    let server = router.bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+
+    assert!(get(addr, "a.test", &a.der).unwrap().ends_with("secure"));
+    assert!(get(addr, "www.b.test", &wildcard.der)
+        .unwrap()
+        .ends_with("secure"));
+    assert!(get(addr, "default.test", &default.der)
+        .unwrap()
+        .ends_with("secure"));
+    // An unknown name gets the default certificate, which the client rejects
+    assert!(get(addr, "c.test", &a.der).is_err());
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_keep_alive() {
+    let localhost = self_signed("localhost");
+    let mut router = router();
+    router.set_tls(TlsConfig::new(localhost.certificate.clone()));
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let mut stream = connect(server.local_addr(), "localhost", &localhost.der).unwrap();
+    let mut buf = [0; 1024];
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let n = stream.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nsecure"));
+    }
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_max_connections() {
+    let localhost = self_signed("localhost");
+    let mut router = router();
+    router.set_tls(TlsConfig::new(localhost.certificate.clone()));
+    router.set_max_connections(1);
+    let server = router.bind("127.0.0.1:0").unwrap();
+
+    let mut first = connect(server.local_addr(), "localhost", &localhost.der).unwrap();
+    first
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(first.read(&mut buf).unwrap() > 0);
+
+    // Over capacity, the connection is closed without a plaintext 503 in the TLS stream
+    let mut second = TcpStream::connect(server.local_addr()).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut response = Vec::new();
+    second.read_to_end(&mut response).unwrap();
+    assert!(response.is_empty());
+
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    assert!(get(server.local_addr(), "localhost", &localhost.der)
+        .unwrap()
+        .ends_with("secure"));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_invalid_pem() {
+    assert!(Certificate::from_pem(b"", b"").is_err());
+    let a = rcgen::generate_simple_self_signed(vec!["a.test".to_string()]).unwrap();
+    let b = rcgen::generate_simple_self_signed(vec!["b.test".to_string()]).unwrap();
+    // The key doesn't belong to the certificate
+    assert!(Certificate::from_pem(
+        a.cert.pem().as_bytes(),
+        b.key_pair.serialize_pem().as_bytes()
+    )
+    .is_err());
+}
@@ -0,0 +1,290 @@
+extern crate waf;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use waf::http::METHOD_GET;
+use waf::server::Server;
+use waf::websocket::{accept_key, Message, WebSocket, WebSocketHandler};
+use waf::Router;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn start() -> Server {
+    let mut router = Router::new();
+    router.set_read_timeout(Some(Duration::from_millis(200)));
+    // Says "tick" whenever the client is quiet for a while
+    router.add_handler(
+        METHOD_GET,
+        "/ticks",
+        WebSocketHandler::new(|ws: &mut WebSocket| {
+            ws.set_read_timeout(Some(Duration::from_millis(50)))?;
+            loop {
+                match ws.recv() {
+                    Ok(Some(Message::Text(text))) => ws.send_text(&text)?,
+                    Ok(Some(_)) => {}
+                    Ok(None) => return Ok(()),
+                    Err(ref e)
+                        if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut =>
+                    {
+                        ws.send_text("tick")?
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }),
+    );
+    router.add_handler(
+        METHOD_GET,
+        "/goodbye",
+        WebSocketHandler::new(|ws: &mut WebSocket| ws.close(1000, &"ä".repeat(100))),
+    );
+    router.add_handler(
+        METHOD_GET,
+        "/echo",
+        WebSocketHandler::new(|ws: &mut WebSocket| {
+            while let Some(message) = ws.recv()? {
+                match message {
+                    Message::Text(_) | Message::Binary(_) => ws.send(&message)?,
+                    _ => {}
+                }
+            }
+            Ok(())
+        })
+        .with_protocols(&["chat"])
+        .with_max_message_size(1024),
+    );
+    router.bind("127.0.0.1:0").unwrap()
+}
+
+// Returns the response head and the stream positioned after it.
+fn handshake(addr: SocketAddr, extra: &str) -> (String, BufReader<TcpStream>) {
+    handshake_path(addr, "/echo", extra)
+}
+
+fn handshake_path(addr: SocketAddr, path: &str, extra: &str) -> (String, BufReader<TcpStream>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n{}\r\n",
+        path, KEY, extra
+    )
+    .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        head.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            return (head, reader);
+        }
+    }
+}
+
+fn connect(addr: SocketAddr) -> BufReader<TcpStream> {
+    connect_path(addr, "/echo")
+}
+
+fn connect_path(addr: SocketAddr, path: &str) -> BufReader<TcpStream> {
+    let (head, reader) = handshake_path(addr, path, "Sec-WebSocket-Version: 13\r\n");
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    reader
+}
+
+fn send_frame(reader: &mut BufReader<TcpStream>, first: u8, payload: &[u8], masked: bool) {
+    let mut frame = vec![first];
+    let mask_bit = if masked { 0x80 } else { 0 };
+    if payload.len() < 126 {
+        frame.push(mask_bit | payload.len() as u8);
+    } else {
+        frame.push(mask_bit | 126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    }
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    if masked {
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    reader.get_mut().write_all(&frame).unwrap();
+}
+
+fn recv_frame(reader: &mut BufReader<TcpStream>) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    assert_eq!(head[1] & 0x80, 0, "server frames are unmasked");
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+#[test]
+fn test_accept_key() {
+    // The example of RFC 6455 1.3
+    assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn test_handshake() {
+    let server = start();
+    let (head, _) = handshake(
+        server.local_addr(),
+        "Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: superchat, chat\r\n",
+    );
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(head.contains("Sec-WebSocket-Protocol: chat\r\n"));
+
+    let (head, _) = handshake(server.local_addr(), "Sec-WebSocket-Version: 8\r\n");
+    assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    assert!(head.contains("Sec-WebSocket-Version: 13\r\n"));
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_echo() {
+    let server = start();
+    let mut ws = connect(server.local_addr());
+
+    send_frame(&mut ws, 0x81, b"hello", true);
+    assert_eq!(recv_frame(&mut ws), (0x81, b"hello".to_vec()));
+
+    let large = vec![7; 300];
+    send_frame(&mut ws, 0x82, &large, true);
+    assert_eq!(recv_frame(&mut ws), (0x82, large));
+
+    // Fragments are joined, with a ping in between answered right away
+    send_frame(&mut ws, 0x01, b"frag", true);
+    send_frame(&mut ws, 0x89, b"are you there", true);
+    assert_eq!(recv_frame(&mut ws), (0x8a, b"are you there".to_vec()));
+    send_frame(&mut ws, 0x80, b"mented", true);
+    assert_eq!(recv_frame(&mut ws), (0x81, b"fragmented".to_vec()));
+
+    send_frame(&mut ws, 0x88, &1000u16.to_be_bytes(), true);
+    assert_eq!(recv_frame(&mut ws), (0x88, 1000u16.to_be_bytes().to_vec()));
+    let mut rest = Vec::new();
+    ws.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    server.shutdown().unwrap();
+}
+
+fn assert_closed_with(ws: &mut BufReader<TcpStream>, code: u16) {
+    let (first, payload) = recv_frame(ws);
+    assert_eq!(first, 0x88);
+    assert_eq!(&payload[..2], &code.to_be_bytes());
+}
+
+#[test]
+fn test_protocol_errors() {
+    let server = start();
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0x81, b"unmasked", false);
+    assert_closed_with(&mut ws, 1002);
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0xc1, b"rsv1", true);
+    assert_closed_with(&mut ws, 1002);
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0x80, b"no start", true);
+    assert_closed_with(&mut ws, 1002);
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0x09, b"fragmented ping", true);
+    assert_closed_with(&mut ws, 1002);
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0x81, &[0xff, 0xfe], true);
+    assert_closed_with(&mut ws, 1007);
+
+    let mut ws = connect(server.local_addr());
+    send_frame(&mut ws, 0x82, &[0; 2000], true);
+    assert_closed_with(&mut ws, 1009);
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_read_timeout_within_frame() {
+    let server = start();
+    let mut ws = connect_path(server.local_addr(), "/ticks");
+
+    // A frame split by pauses longer than the read timeout
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x81, 0x80 | 5];
+    frame.extend_from_slice(&mask);
+    frame.extend(b"hello".iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    for part in [&frame[..1], &frame[1..4], &frame[4..8], &frame[8..]].iter() {
+        ws.get_mut().write_all(part).unwrap();
+        thread::sleep(Duration::from_millis(120));
+    }
+
+    let mut messages = Vec::new();
+    while messages.last() != Some(&b"hello".to_vec()) {
+        let (first, payload) = recv_frame(&mut ws);
+        assert_eq!(first, 0x81);
+        assert!(payload == b"tick" || payload == b"hello");
+        messages.push(payload);
+    }
+    assert!(messages.len() > 1);
+
+    send_frame(&mut ws, 0x88, &1000u16.to_be_bytes(), true);
+    loop {
+        let (first, _) = recv_frame(&mut ws);
+        if first == 0x88 {
+            break;
+        }
+    }
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_close_reason_is_truncated() {
+    let server = start();
+    let mut ws = connect_path(server.local_addr(), "/goodbye");
+    let (first, payload) = recv_frame(&mut ws);
+    assert_eq!(first, 0x88);
+    assert_eq!(&payload[..2], &1000u16.to_be_bytes());
+    // Cut at a character boundary within the 125 bytes of a control frame
+    let reason = std::str::from_utf8(&payload[2..]).unwrap();
+    assert_eq!(reason, "ä".repeat(61));
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn test_idle_longer_than_read_timeout() {
+    let server = start();
+    let mut ws = connect(server.local_addr());
+
+    // The server's read timeout is for request bodies, not for quiet WebSocket sessions
+    thread::sleep(Duration::from_millis(500));
+    send_frame(&mut ws, 0x81, b"still there", true);
+    assert_eq!(recv_frame(&mut ws), (0x81, b"still there".to_vec()));
+
+    send_frame(&mut ws, 0x88, &1000u16.to_be_bytes(), true);
+    assert_closed_with(&mut ws, 1000);
+    server.shutdown().unwrap();
+}